@@ -1,19 +1,26 @@
 
+#[cfg(windows)]
 use serde::{Serialize,Deserialize};
+#[cfg(windows)]
 use structopt::StructOpt;
+#[cfg(windows)]
 use tokio::sync::watch;
+#[cfg(windows)]
 use winsvc::{
     std_cli::{Command,ServiceDetail,LoggingConfig},
     async_service_main::InitializationToken,
 };
 
+#[cfg(windows)]
 struct Service;
 
-#[derive(StructOpt,Serialize,Deserialize,Debug)]
+#[cfg(windows)]
+#[derive(StructOpt,Serialize,Deserialize,Debug,Clone)]
 struct ServiceConfig{
   message: String
 }
 
+#[cfg(windows)]
 async fn run_for_a_while(config: ServiceConfig, mut running: watch::Receiver<bool>) {
 
     tracing::info!("entering {}", config.message);
@@ -26,16 +33,7 @@ async fn run_for_a_while(config: ServiceConfig, mut running: watch::Receiver<boo
 
 }
 
-fn run_forever(config: ServiceConfig) {
-    tracing_subscriber::fmt::init();
-    let (_set_running,running) = tokio::sync::watch::channel(true);
-    tokio::runtime::Builder::new_multi_thread()
-        .enable_all()
-        .build()
-        .unwrap()
-        .block_on(run_for_a_while(config, running))
-}
-
+#[cfg(windows)]
 async fn service_main(
     config: ServiceConfig,
     init: InitializationToken,
@@ -45,6 +43,7 @@ async fn service_main(
     run_for_a_while(config, running).await
 }
 
+#[cfg(windows)]
 impl ServiceDetail for Service {
 
     const SERVICE_IDENTIFIER: &'static str = "winsvc-test-service-1";
@@ -53,7 +52,10 @@ impl ServiceDetail for Service {
     type Config = ServiceConfig;
 
     fn run_local(config: Self::Config) {
-        run_forever(config);
+        // logging is already set up when `run-as-service` falls back to the
+        // console
+        let _ = tracing_subscriber::fmt::try_init();
+        winsvc::async_service_main::run_console(Self::SERVICE_IDENTIFIER, config, service_main);
     }
 
     fn run_as_service(log_config: LoggingConfig) {
@@ -61,10 +63,21 @@ impl ServiceDetail for Service {
         std::panic::set_hook(Box::new(|panic: &std::panic::PanicInfo<'_>| -> () {
             tracing::error!("panic: {}", panic);
         }));
-        winsvc::async_service_dispatcher!{ "cpm-proxy" => service_main }
+        use winsvc::service_dispatcher::{DispatcherOptions,WorkingDirectory};
+        let options = DispatcherOptions::default().working_directory(WorkingDirectory::ExecutableDirectory);
+        winsvc::async_service_dispatcher!{ <Service as ServiceDetail>::SERVICE_IDENTIFIER => service_main, options, else || {
+            let config = winsvc::service_configuration::load(&Self::service_name()).expect("while loading service configuration");
+            Self::run_local(config)
+        } }
     }
 }
 
+#[cfg(windows)]
 fn main() {
   Command::<Service>::execute()
 }
+
+#[cfg(not(windows))]
+fn main() {
+  eprintln!("windows services are only supported on windows");
+}
@@ -0,0 +1,139 @@
+//! Accounts a service can run as
+
+use std::fmt;
+
+/// the account a service logs on as
+#[derive(Clone,Eq,PartialEq,Default)]
+pub enum Account {
+    /// the all powerful `LocalSystem` account
+    LocalSystem,
+    /// `NT AUTHORITY\LocalService`, minimal rights and anonymous on the network
+    LocalService,
+    /// `NT AUTHORITY\NetworkService`, minimal rights and the machine account
+    /// on the network
+    #[default]
+    NetworkService,
+    /// the virtual account `NT SERVICE\<service name>` created for the service
+    Virtual(String),
+    /// a group managed service account, `DOMAIN\name$`
+    Managed(String),
+    /// an ordinary user account, `DOMAIN\name` or `.\name`
+    User{name: String, password: String},
+}
+
+impl Account {
+    /// the virtual account of the named service
+    pub fn virtual_for(service_name: &str) -> Self {
+        Self::Virtual(service_name.into())
+    }
+
+    /// interpret an account name the way the service control manager does
+    ///
+    /// The built in accounts are recognised with or without their
+    /// `NT AUTHORITY\` prefix, names under `NT SERVICE\` are virtual accounts
+    /// and names ending in `$` managed service accounts. Anything else is a
+    /// user account and uses `password`.
+    pub fn from_name(name: &str, password: Option<String>) -> Self {
+        let unqualified = strip_prefix_ignore_case(name, "NT AUTHORITY\\").unwrap_or(name);
+
+        if unqualified.eq_ignore_ascii_case("LocalSystem") || unqualified.eq_ignore_ascii_case("SYSTEM") {
+            Self::LocalSystem
+        } else if unqualified.eq_ignore_ascii_case("LocalService") {
+            Self::LocalService
+        } else if unqualified.eq_ignore_ascii_case("NetworkService") {
+            Self::NetworkService
+        } else if let Some(service_name) = strip_prefix_ignore_case(name, "NT SERVICE\\") {
+            Self::Virtual(service_name.into())
+        } else if name.ends_with('$') {
+            Self::Managed(name.into())
+        } else {
+            Self::User{ name: name.into(), password: password.unwrap_or_default() }
+        }
+    }
+
+    /// the name passed to the service control manager as the start name
+    pub fn name(&self) -> String {
+        match self {
+            Self::LocalSystem => "LocalSystem".into(),
+            Self::LocalService => "NT AUTHORITY\\LocalService".into(),
+            Self::NetworkService => "NT AUTHORITY\\NetworkService".into(),
+            Self::Virtual(service_name) => format!("NT SERVICE\\{}", service_name),
+            Self::Managed(name) => name.clone(),
+            Self::User{name, ..} => name.clone(),
+        }
+    }
+
+    /// the password, only user accounts have one
+    pub fn password(&self) -> Option<&str> {
+        match self {
+            Self::User{password, ..} => Some(password),
+            _ => None,
+        }
+    }
+
+    /// the password to give `ChangeServiceConfigW` when switching to this
+    /// account, empty for the built in accounts and none at all for virtual
+    /// and managed accounts, which the call rejects a password for
+    pub fn change_password(&self) -> Option<&str> {
+        match self {
+            Self::LocalSystem | Self::LocalService | Self::NetworkService => Some(""),
+            Self::Virtual(_) | Self::Managed(_) => None,
+            Self::User{password, ..} => Some(password),
+        }
+    }
+}
+
+impl fmt::Debug for Account {
+    // keep passwords out of logs
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::User{name, ..} => f.debug_struct("User").field("name", name).finish_non_exhaustive(),
+            _ => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+impl fmt::Display for Account {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name())
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(name: &'a str, prefix: &str) -> Option<&'a str> {
+    match name.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(prefix) => Some(&name[prefix.len()..]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn built_in_accounts() {
+        assert_eq!(Account::from_name("LocalSystem", None), Account::LocalSystem);
+        assert_eq!(Account::from_name("localservice", None), Account::LocalService);
+        assert_eq!(Account::from_name("NT AUTHORITY\\NetworkService", None), Account::NetworkService);
+        assert_eq!(Account::from_name("nt authority\\system", None), Account::LocalSystem);
+    }
+
+    #[test]
+    fn service_accounts() {
+        assert_eq!(Account::from_name("NT SERVICE\\hello", None), Account::virtual_for("hello"));
+        assert_eq!(Account::virtual_for("hello").name(), "NT SERVICE\\hello");
+        assert_eq!(Account::from_name("CORP\\svc-hello$", None), Account::Managed("CORP\\svc-hello$".into()));
+        assert_eq!(Account::Managed("CORP\\svc-hello$".into()).password(), None);
+        assert_eq!(Account::Managed("CORP\\svc-hello$".into()).change_password(), None);
+        assert_eq!(Account::virtual_for("hello").change_password(), None);
+        assert_eq!(Account::LocalService.change_password(), Some(""));
+    }
+
+    #[test]
+    fn user_accounts() {
+        let account = Account::from_name(".\\hello", Some("secret".into()));
+        assert_eq!(account.name(), ".\\hello");
+        assert_eq!(account.password(), Some("secret"));
+        assert!(!format!("{:?}", account).contains("secret"));
+    }
+}
@@ -1,12 +1,84 @@
-
-use std::sync::{Arc,Mutex,atomic::{Ordering::Relaxed,AtomicIsize}};
+use std::sync::{Arc,Mutex,mpsc,atomic::{Ordering::Relaxed,AtomicBool,AtomicIsize}};
 use std::future::Future;
 use std::time::Duration;
-use tokio::sync::watch;
+use std::ffi::OsString;
+use std::io::BufRead;
+use tokio::sync::{watch,mpsc::{unbounded_channel,UnboundedReceiver,UnboundedSender}};
+
+use winapi::{
+    shared::minwindef::{BOOL,DWORD,TRUE,FALSE},
+    um::{
+        consoleapi::SetConsoleCtrlHandler,
+        wincon::{CTRL_C_EVENT,CTRL_BREAK_EVENT},
+    },
+};
 
 use crate::service_dispatcher::*;
 
-pub struct InitializationToken(Arc<Mutex<ServiceStatus>>);
+/// where the service reports its status, the service control manager or the
+/// console when hosted by `run_console`
+enum Reporter {
+    Service(ServiceStatus),
+    Console(String),
+}
+
+impl Reporter {
+    fn starting(&mut self, wait_hint: Duration) -> crate::Result<()> {
+        match self {
+            Self::Service(status) => status.starting(wait_hint),
+            Self::Console(name) => {
+                println!("{}: starting (wait hint {:?})", name, wait_hint);
+                Ok(())
+            },
+        }
+    }
+
+    fn running(&mut self) -> crate::Result<()> {
+        match self {
+            Self::Service(status) => status.running(),
+            Self::Console(name) => {
+                println!("{}: running", name);
+                Ok(())
+            },
+        }
+    }
+
+    fn paused(&mut self) -> crate::Result<()> {
+        match self {
+            Self::Service(status) => status.paused(),
+            Self::Console(name) => {
+                println!("{}: paused", name);
+                Ok(())
+            },
+        }
+    }
+
+    fn stopped(&mut self) -> crate::Result<()> {
+        match self {
+            Self::Service(status) => status.stopped(),
+            Self::Console(name) => {
+                println!("{}: stopped", name);
+                Ok(())
+            },
+        }
+    }
+
+    fn accept_param_change(&mut self) -> crate::Result<()> {
+        match self {
+            Self::Service(status) => status.accept_param_change(),
+            Self::Console(_) => Ok(()),
+        }
+    }
+
+    fn start_reason(&self) -> crate::Result<StartReason> {
+        match self {
+            Self::Service(status) => status.start_reason(),
+            Self::Console(_) => Ok(StartReason::DEMAND),
+        }
+    }
+}
+
+pub struct InitializationToken(Arc<Mutex<Reporter>>,Arc<[OsString]>,ServiceControls);
 
 impl<'a> InitializationToken {
     /// if initialization is going to take more than *a second* call this
@@ -15,28 +87,73 @@ impl<'a> InitializationToken {
         self.0.lock().unwrap().starting(wait_hint).unwrap();
     }
 
+    /// the start parameters, the first is the service name
+    pub fn arguments(&self) -> &[OsString] {
+        &self.1
+    }
+
+    /// why the service was started, e.g. by one of its triggers
+    pub fn start_reason(&self) -> crate::Result<StartReason> {
+        self.0.lock().unwrap().start_reason()
+    }
+
+    /// the parameter change and user defined controls sent to the service,
+    /// until this is first called they are not accepted
+    pub fn controls(&self) -> ServiceControls {
+        self.2.listening.store(true, Relaxed);
+        self.0.lock().unwrap().accept_param_change().unwrap();
+        self.2.clone()
+    }
+
     /// notify system that service initialization is complete
     pub fn complete(self) {
         self.0.lock().unwrap().running().unwrap()
     }
 }
 
-pub unsafe fn raw_async_service_main_wrapper<T,U,V>(name: &str, _argc: u32, _argv: *mut *mut u16, function: T) where
-    T: Copy+FnOnce(V,InitializationToken,watch::Receiver<bool>)->U,
-    U: Future<Output=()>,
-    V: serde::de::DeserializeOwned,
-{
-    const RUN_LEVEL_STOPPED : isize = 0;
-    const RUN_LEVEL_PAUSED  : isize = 1;
-    const RUN_LEVEL_STARTED : isize = 2;
+/// receives the `ParamChange` and `UserDefined` controls, the ones the run
+/// loop leaves to the service
+#[derive(Clone)]
+pub struct ServiceControls {
+    listening: Arc<AtomicBool>,
+    events: Arc<tokio::sync::Mutex<UnboundedReceiver<ServiceEvent>>>,
+}
+
+impl ServiceControls {
+    /// the next control, `None` once the control handler is gone
+    pub async fn recv(&self) -> Option<ServiceEvent> {
+        self.events.lock().await.recv().await
+    }
+}
+
+const RUN_LEVEL_STOPPED : isize = 0;
+const RUN_LEVEL_PAUSED  : isize = 1;
+const RUN_LEVEL_STARTED : isize = 2;
+
+/// the state shared between the control handler and the run loop
+struct Controls {
+    requested_state: Arc<AtomicIsize>,
+    running: watch::Receiver<bool>,
+    paused: watch::Receiver<bool>,
+    service_controls: ServiceControls,
+}
+
+fn control_handler() -> (Controls, impl FnMut(ServiceEvent)->ServiceEventResult) {
 
     let requested_state = Arc::new(AtomicIsize::new(RUN_LEVEL_STARTED));
 
     let (set_running,running) = watch::channel(true);
-    let (set_paused,mut paused) = watch::channel(false);
+    let (set_paused,paused) = watch::channel(false);
+
+    let (send_control, events): (UnboundedSender<ServiceEvent>, _) = unbounded_channel();
+    let service_controls = ServiceControls{
+        listening: Arc::new(AtomicBool::new(false)),
+        events: Arc::new(tokio::sync::Mutex::new(events)),
+    };
 
     let handler = {
         let requested_state = requested_state.clone();
+        let listening = service_controls.listening.clone();
         move |event: ServiceEvent| -> ServiceEventResult {
             tracing::info!("service control request: {:?}", event);
             match event {
@@ -66,12 +183,34 @@ pub unsafe fn raw_async_service_main_wrapper<T,U,V>(name: &str, _argc: u32, _arg
                     set_paused.send(false).unwrap();
                     Ok(())
                 },
-                _ => Err(SERVICE_EVENT_NOT_IMPLEMENTED)
+                // the last reported status is what the manager wants
+                ServiceEvent::Interrogate => Ok(()),
+                ServiceEvent::ParamChange | ServiceEvent::UserDefined(_) => {
+                    if !listening.load(Relaxed) {
+                        return Err(SERVICE_EVENT_NOT_IMPLEMENTED);
+                    }
+                    send_control.send(event).map_err(|_|SERVICE_EVENT_NOT_IMPLEMENTED)
+                },
             }
         }
     };
 
-    let status = Arc::new(Mutex::new(register_service_ctrl_handler(name, handler).unwrap()));
+    (Controls{ requested_state, running, paused, service_controls }, handler)
+}
+
+/// run `function` each time the service is started or continued until it is
+/// stopped, reporting each transition
+fn run_loop<T,U,V>(
+    status: Arc<Mutex<Reporter>>,
+    controls: Controls,
+    arguments: Arc<[OsString]>,
+    mut load_config: impl FnMut()->V,
+    function: T,
+) where
+    T: Copy+FnOnce(V,InitializationToken,watch::Receiver<bool>)->U,
+    U: Future<Output=()>,
+{
+    let Controls{ requested_state, running, mut paused, service_controls } = controls;
 
     loop {
 
@@ -95,12 +234,12 @@ pub unsafe fn raw_async_service_main_wrapper<T,U,V>(name: &str, _argc: u32, _arg
             },
             RUN_LEVEL_STARTED => {
                 tracing::trace!("entering started state");
-                let config = crate::service_configuration::load::<V>(name).unwrap();
+                let config = load_config();
                 tokio::runtime::Builder::new_multi_thread()
                     .enable_all()
                     .build()
                     .unwrap()
-                    .block_on(function(config,InitializationToken(status.clone()), running.clone()));
+                    .block_on(function(config,InitializationToken(status.clone(), arguments.clone(), service_controls.clone()), running.clone()));
                 tracing::trace!("exiting started state");
             },
             _ => panic!()
@@ -108,9 +247,118 @@ pub unsafe fn raw_async_service_main_wrapper<T,U,V>(name: &str, _argc: u32, _arg
     }
 }
 
+/// run the async service main `function` for the service `name`, for the
+/// raw service main `async_service_dispatcher!` defines
+///
+/// # Safety
+///
+/// `argc` and `argv` must be what the service control manager passed the
+/// service main, see `ServiceArgs::from_raw`.
+pub unsafe fn raw_async_service_main_wrapper<T,U,V>(name: &str, argc: u32, argv: *mut *mut u16, function: T) where
+    T: Copy+FnOnce(V,InitializationToken,watch::Receiver<bool>)->U,
+    U: Future<Output=()>,
+    V: serde::de::DeserializeOwned,
+{
+    let arguments: Arc<[OsString]> = ServiceArgs::from_raw(argc, argv).arguments().into();
+
+    let service_name = crate::service_name::ServiceName::new(name).unwrap();
+
+    let (controls, handler) = control_handler();
+
+    let status = Arc::new(Mutex::new(Reporter::Service(register_service_ctrl_handler(name, handler).unwrap())));
+
+    run_loop(
+        status,
+        controls,
+        arguments,
+        || crate::service_configuration::load::<V>(&service_name).unwrap(),
+        function,
+    );
+}
+
+/// receives the console control events while `run_console` is hosting
+static CONSOLE_EVENTS: Mutex<Option<mpsc::Sender<ServiceEvent>>> = Mutex::new(None);
+
+unsafe extern "system" fn console_ctrl_handler(ctrl_type: DWORD) -> BOOL {
+    match ctrl_type {
+        CTRL_C_EVENT | CTRL_BREAK_EVENT => {
+            match &*CONSOLE_EVENTS.lock().unwrap() {
+                Some(events) => { let _ = events.send(ServiceEvent::Stop); TRUE },
+                None => FALSE,
+            }
+        },
+        _ => FALSE,
+    }
+}
+
+/// parse a line typed into the console host
+fn parse_console_command(line: &str) -> Option<ServiceEvent> {
+    match line.trim() {
+        "stop" | "s" | "q" => Some(ServiceEvent::Stop),
+        "pause" | "p" => Some(ServiceEvent::Pause),
+        "continue" | "c" => Some(ServiceEvent::Continue),
+        "paramchange" => Some(ServiceEvent::ParamChange),
+        "interrogate" => Some(ServiceEvent::Interrogate),
+        code => code.parse::<u8>().ok().filter(|code|*code >= 128).map(ServiceEvent::UserDefined),
+    }
+}
+
+/// run the async service main in the console as the service dispatcher
+/// would, for development
+///
+/// Ctrl-C and Ctrl-Break stop the service, lines read from stdin send
+/// `pause`, `continue`, `stop`, `paramchange`, `interrogate` or a user
+/// defined control code (128-255). Status transitions are printed.
+pub fn run_console<T,U,V>(name: &str, config: V, function: T) where
+    T: Copy+FnOnce(V,InitializationToken,watch::Receiver<bool>)->U,
+    U: Future<Output=()>,
+    V: Clone,
+{
+    let (controls, mut handler) = control_handler();
+
+    let (send_event, events) = mpsc::channel();
+
+    *CONSOLE_EVENTS.lock().unwrap() = Some(send_event.clone());
+    unsafe { SetConsoleCtrlHandler(Some(console_ctrl_handler), TRUE) };
+
+    std::thread::spawn(move || {
+        println!("commands: stop, pause, continue, paramchange, interrogate, 128-255");
+        for line in std::io::stdin().lock().lines() {
+            let line = match line { Ok(line) => line, Err(_) => break };
+            match parse_console_command(&line) {
+                Some(event) => if send_event.send(event).is_err() { break },
+                None => println!("unknown command: {}", line.trim()),
+            }
+        }
+    });
+
+    // deliver controls one at a time, as the dispatcher does
+    std::thread::spawn(move || {
+        for event in events {
+            if let Err(error) = handler(event.clone()) {
+                println!("{:?} not accepted: {}", event, error);
+            }
+        }
+    });
+
+    let arguments: Arc<[OsString]> = vec![OsString::from(name)].into();
+    let status = Arc::new(Mutex::new(Reporter::Console(name.into())));
+
+    run_loop(status, controls, arguments, || config.clone(), function);
+
+    unsafe { SetConsoleCtrlHandler(Some(console_ctrl_handler), FALSE) };
+    *CONSOLE_EVENTS.lock().unwrap() = None;
+}
+
+/// connect to the service control manager and run the async `$function` as
+/// the service, with `else` calling `$console` instead when the process was
+/// not started as a service
+///
+/// `$name` is used in the service main this defines, so it may name a
+/// constant by its full path but not through `Self`.
 #[macro_export]
 macro_rules! async_service_dispatcher {
-    ( $name:literal => $function:ident ) => {
+    ( $name:expr => $function:ident ) => {
         {
             use $crate::service_dispatcher::{
                 start_service_ctrl_dispatcher_raw,
@@ -126,4 +374,36 @@ macro_rules! async_service_dispatcher {
             start_service_ctrl_dispatcher_raw($name, service_main_raw).unwrap()
         }
     };
+    ( $name:expr => $function:ident, $options:expr ) => {
+        {
+            use $crate::service_dispatcher::{
+                start_service_ctrl_dispatcher_with,
+            };
+            use $crate::async_service_main::{
+                raw_async_service_main_wrapper,
+            };
+
+            unsafe extern "system" fn service_main_raw(argc: u32, argv: *mut *mut u16) {
+                raw_async_service_main_wrapper($name, argc, argv, $function);
+            }
+
+            start_service_ctrl_dispatcher_with($name, service_main_raw, &$options).unwrap()
+        }
+    };
+    ( $name:expr => $function:ident, $options:expr, else $console:expr ) => {
+        {
+            use $crate::service_dispatcher::{
+                start_service_ctrl_dispatcher_or_else,
+            };
+            use $crate::async_service_main::{
+                raw_async_service_main_wrapper,
+            };
+
+            unsafe extern "system" fn service_main_raw(argc: u32, argv: *mut *mut u16) {
+                raw_async_service_main_wrapper($name, argc, argv, $function);
+            }
+
+            start_service_ctrl_dispatcher_or_else($name, service_main_raw, &$options, $console).unwrap()
+        }
+    };
 }
@@ -0,0 +1,291 @@
+//! Command line quoting
+//!
+//! Windows passes a process its command line as a single string, which the
+//! program (here via `CommandLineToArgvW`) splits back into arguments. The
+//! service control manager launches services from such a string, so the
+//! executable path and arguments must be quoted to survive that split
+//! unchanged. This module implements both directions.
+//!
+//! The executable path follows different rules than the arguments after it:
+//! it ends at the next quote when it starts with one, otherwise at the next
+//! space or tab, and backslashes are never special. Paths can not contain
+//! quotes, so it is always quoted here, which also avoids the unquoted service
+//! path problem when installed under a directory containing spaces.
+
+use std::fmt;
+
+const SPACE: u16 = b' ' as u16;
+const TAB: u16 = b'\t' as u16;
+const QUOTE: u16 = b'"' as u16;
+const BACKSLASH: u16 = b'\\' as u16;
+
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub enum QuoteError {
+    /// the executable path contains a `"`, which can not be represented
+    QuoteInProgram,
+    /// a NUL would terminate the command line early
+    ContainsNul,
+}
+
+impl fmt::Display for QuoteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QuoteError::QuoteInProgram => f.write_str("executable path contains a quote"),
+            QuoteError::ContainsNul => f.write_str("command line contains a NUL character"),
+        }
+    }
+}
+
+impl std::error::Error for QuoteError {}
+
+fn needs_quotes(argument: &[u16]) -> bool {
+    argument.is_empty() || argument.iter().any(|&c| c == SPACE || c == TAB || c == QUOTE)
+}
+
+/// append `argument` to `command_line`, quoting and escaping it as needed
+pub fn quote_argument_wide(argument: &[u16], command_line: &mut Vec<u16>) {
+    if !needs_quotes(argument) {
+        command_line.extend_from_slice(argument);
+        return;
+    }
+
+    command_line.push(QUOTE);
+
+    let mut backslashes = 0;
+    for &c in argument {
+        match c {
+            BACKSLASH => backslashes += 1,
+            QUOTE => {
+                // escape the preceding backslashes and the quote itself
+                command_line.extend(std::iter::repeat_n(BACKSLASH, backslashes * 2 + 1));
+                command_line.push(QUOTE);
+                backslashes = 0;
+            },
+            _ => {
+                command_line.extend(std::iter::repeat_n(BACKSLASH, backslashes));
+                command_line.push(c);
+                backslashes = 0;
+            },
+        }
+    }
+
+    // backslashes before the closing quote must be escaped
+    command_line.extend(std::iter::repeat_n(BACKSLASH, backslashes * 2));
+    command_line.push(QUOTE);
+}
+
+/// build a command line that launches `program` with `arguments`
+pub fn join_wide<A: AsRef<[u16]>>(program: &[u16], arguments: &[A]) -> Result<Vec<u16>,QuoteError> {
+    if program.contains(&QUOTE) {
+        return Err(QuoteError::QuoteInProgram);
+    }
+
+    if program.contains(&0) || arguments.iter().any(|argument|argument.as_ref().contains(&0)) {
+        return Err(QuoteError::ContainsNul);
+    }
+
+    let mut command_line = Vec::with_capacity(program.len() + 2);
+
+    command_line.push(QUOTE);
+    command_line.extend_from_slice(program);
+    command_line.push(QUOTE);
+
+    for argument in arguments {
+        command_line.push(SPACE);
+        quote_argument_wide(argument.as_ref(), &mut command_line);
+    }
+
+    Ok(command_line)
+}
+
+/// split a command line into the executable path and its arguments exactly
+/// as `CommandLineToArgvW` does
+pub fn split_wide(command_line: &[u16]) -> Vec<Vec<u16>> {
+    let mut arguments = Vec::new();
+    let mut rest = command_line;
+
+    if rest.is_empty() {
+        return arguments;
+    }
+
+    // the executable path, no escapes apply
+    let program = if rest[0] == QUOTE {
+        let end = rest[1..].iter().position(|&c|c == QUOTE).map_or(rest.len(), |end|end + 1);
+        let program = rest[1..end].to_vec();
+        rest = &rest[(end + 1).min(rest.len())..];
+        program
+    } else {
+        let end = rest.iter().position(|&c|c == SPACE || c == TAB).unwrap_or(rest.len());
+        let program = rest[..end].to_vec();
+        rest = &rest[end..];
+        program
+    };
+
+    arguments.push(program);
+
+    let skip_blanks = |rest: &[u16]| rest.iter().position(|&c|c != SPACE && c != TAB).unwrap_or(rest.len());
+
+    rest = &rest[skip_blanks(rest)..];
+
+    if rest.is_empty() {
+        return arguments;
+    }
+
+    let mut argument = Vec::new();
+    let mut backslashes = 0;
+    let mut quotes = 0;
+    let mut index = 0;
+
+    while index < rest.len() {
+        let c = rest[index];
+        if (c == SPACE || c == TAB) && quotes == 0 {
+            arguments.push(std::mem::take(&mut argument));
+            index += skip_blanks(&rest[index..]);
+            backslashes = 0;
+            if index == rest.len() {
+                return arguments;
+            }
+        } else if c == BACKSLASH {
+            argument.push(c);
+            backslashes += 1;
+            index += 1;
+        } else if c == QUOTE {
+            // 2n backslashes yield n and the quote toggles quoting, 2n+1
+            // yield n and a literal quote
+            argument.truncate(argument.len() - backslashes / 2);
+            if backslashes % 2 == 0 {
+                quotes += 1;
+            } else {
+                argument.pop();
+                argument.push(QUOTE);
+            }
+            index += 1;
+            backslashes = 0;
+
+            // every third quote in a run is literal and ends quoting
+            while index < rest.len() && rest[index] == QUOTE {
+                quotes += 1;
+                if quotes == 3 {
+                    argument.push(QUOTE);
+                    quotes = 0;
+                }
+                index += 1;
+            }
+            if quotes == 2 {
+                quotes = 0;
+            }
+        } else {
+            argument.push(c);
+            backslashes = 0;
+            index += 1;
+        }
+    }
+
+    arguments.push(argument);
+    arguments
+}
+
+/// quote a single argument
+pub fn quote_argument(argument: &str) -> String {
+    let mut command_line = Vec::new();
+    quote_argument_wide(&argument.encode_utf16().collect::<Vec<_>>(), &mut command_line);
+    String::from_utf16(&command_line).unwrap()
+}
+
+/// build a command line that launches `program` with `arguments`
+pub fn join<A: AsRef<str>>(program: &str, arguments: &[A]) -> Result<String,QuoteError> {
+    let program: Vec<u16> = program.encode_utf16().collect();
+    let arguments: Vec<Vec<u16>> = arguments.iter().map(|argument|argument.as_ref().encode_utf16().collect()).collect();
+    join_wide(&program, &arguments).map(|command_line|String::from_utf16(&command_line).unwrap())
+}
+
+/// split a command line into the executable path and its arguments
+pub fn split(command_line: &str) -> Vec<String> {
+    let command_line: Vec<u16> = command_line.encode_utf16().collect();
+    split_wide(&command_line)
+        .into_iter()
+        .map(|argument|String::from_utf16(&argument).unwrap())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use quickcheck::{quickcheck, TestResult};
+
+    fn check_split(command_line: &str, expected: &[&str]) {
+        assert_eq!(split(command_line), expected, "splitting {:?}", command_line);
+    }
+
+    #[test]
+    fn documented_examples() {
+        // from the parsing table in the CommandLineToArgvW documentation
+        check_split(r#"p "abc" d e"#, &["p", "abc", "d", "e"]);
+        check_split(r#"p a\\\b d"e f"g h"#, &["p", r"a\\\b", "de fg", "h"]);
+        check_split(r#"p a\\\"b c d"#, &["p", r#"a\"b"#, "c", "d"]);
+        check_split(r#"p a\\\\"b c" d e"#, &["p", r"a\\b c", "d", "e"]);
+    }
+
+    #[test]
+    fn consecutive_quotes() {
+        check_split(r#"p a"b"" c d"#, &["p", r#"ab""#, "c", "d"]);
+        check_split(r#"p """a b""""#, &["p", r#""a"#, r#"b""#]);
+        check_split(r#"p "" """#, &["p", "", ""]);
+    }
+
+    #[test]
+    fn program_path() {
+        check_split(r#""C:\Program Files\svc.exe" run"#, &[r"C:\Program Files\svc.exe", "run"]);
+        check_split(r#"C:\a\b"c d"#, &[r#"C:\a\b"c"#, "d"]);
+        check_split(r#""C:\a\" b"#, &[r"C:\a\", "b"]);
+        check_split("svc.exe   ", &["svc.exe"]);
+        check_split("", &[]);
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote_argument("plain"), "plain");
+        assert_eq!(quote_argument(""), r#""""#);
+        assert_eq!(quote_argument("a b"), r#""a b""#);
+        assert_eq!(quote_argument(r"C:\dir with space\"), r#""C:\dir with space\\""#);
+        assert_eq!(quote_argument(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(quote_argument(r"back\slash"), r"back\slash");
+    }
+
+    #[test]
+    fn joins_program_path() {
+        assert_eq!(
+            join(r"C:\Program Files\svc.exe", &["run-as-service", "--log-file", r"C:\log dir\svc.log"]).unwrap(),
+            r#""C:\Program Files\svc.exe" run-as-service --log-file "C:\log dir\svc.log""#,
+        );
+        assert_eq!(join(r#"C:\a"b.exe"#, &[] as &[&str]), Err(QuoteError::QuoteInProgram));
+        assert_eq!(join(r"C:\a.exe", &["a\0b"]), Err(QuoteError::ContainsNul));
+    }
+
+    quickcheck! {
+        fn round_trip(program: String, arguments: Vec<String>) -> TestResult {
+            if program.is_empty() || program.contains(['"', '\0']) || arguments.iter().any(|argument|argument.contains('\0')) {
+                return TestResult::discard();
+            }
+
+            let command_line = join(&program, &arguments).unwrap();
+            let mut expected = vec![program];
+            expected.extend(arguments);
+
+            TestResult::from_bool(split(&command_line) == expected)
+        }
+
+        fn round_trip_special_characters(arguments: Vec<Vec<u8>>) -> bool {
+            // bias towards the characters that matter to the quoting rules
+            let arguments: Vec<String> = arguments.iter()
+                .map(|argument|argument.iter().map(|c|['\\', '"', ' ', '\t', 'a'][*c as usize % 5]).collect())
+                .collect();
+
+            let command_line = join("svc.exe", &arguments).unwrap();
+            let mut expected = vec![String::from("svc.exe")];
+            expected.extend(arguments);
+
+            split(&command_line) == expected
+        }
+    }
+}
@@ -5,7 +5,7 @@
 //! end it provides several features. There is a built it command line interface,
 //! with easy configuration management via `structop` and 'serde'.
 //!
-//! ```
+//! ```ignore
 //! use tokio::select;
 //! use winsvc::QuitSignal;
 //! use structopt::StructOpt;
@@ -25,20 +25,68 @@
 //! ```
 
 
+/// a typed set of flags, e.g. access rights
+#[cfg(windows)]
+macro_rules! bit_flags {
+    ( $(#[$meta:meta])* $name:ident { $( $(#[$cmeta:meta])* $cname:ident = $value:expr ),+ $(,)? } ) => {
+
+        $(#[$meta])*
+        #[derive(Copy,Clone,Debug,Eq,PartialEq)]
+        pub struct $name(u32);
+
+        impl $name {
+            $( $(#[$cmeta])* pub const $cname: Self = Self($value); )+
+
+            /// build from raw bits
+            pub const fn from_raw(bits: u32) -> Self { Self(bits) }
+
+            pub const fn into_raw(self) -> u32 { self.0 }
+
+            /// true if every flag in `other` is also in `self`
+            pub const fn contains(self, other: Self) -> bool { self.0 & other.0 == other.0 }
+        }
+
+        impl std::ops::BitOr for $name {
+            type Output = Self;
+            fn bitor(self, other: Self) -> Self { Self(self.0 | other.0) }
+        }
+
+        impl std::ops::BitOrAssign for $name {
+            fn bitor_assign(&mut self, other: Self) { self.0 |= other.0 }
+        }
+    }
+}
+
+#[cfg(windows)]
 pub mod service_dispatcher;
+#[cfg(windows)]
+pub use service_dispatcher::is_running_as_service;
+#[cfg(windows)]
 pub mod service_control_manager;
+#[cfg(windows)]
+pub mod service_notify;
+#[cfg(windows)]
+pub mod service_security;
+pub mod service_trigger;
 
-#[cfg(feature = "async_main")]
+#[cfg(all(windows, feature = "async_main"))]
 pub mod async_service_main;
 
 #[cfg(feature = "serde_config")]
 pub mod serde_config;
 
-#[cfg(feature = "std_cli")]
+#[cfg(all(windows, feature = "std_cli"))]
 pub mod std_cli;
 
+pub mod sddl;
+pub mod command_line;
+pub mod account;
+pub mod service_name;
+
+use std::fmt;
+
+#[cfg(windows)]
 use std::{
-    fmt,
     ptr::null_mut,
     ffi::{OsStr,OsString},
     path::PathBuf,
@@ -46,6 +94,7 @@ use std::{
     os::windows::ffi::{OsStrExt,OsStringExt},
 };
 
+#[cfg(windows)]
 use winapi::{
     um::{
         winnt::WCHAR,
@@ -73,10 +122,12 @@ impl IsTrue for i32 { fn is_true(&self) -> bool { *self != 0 } }
 
 impl Error {
 
+    #[cfg(windows)]
     pub fn from_last() -> Self {
         Self{code:unsafe{GetLastError()}}
     }
 
+    #[cfg(windows)]
     pub fn check_true(value: impl IsTrue) -> Result<()> {
         if value.is_true() {
             Ok(())
@@ -93,6 +144,7 @@ impl Error {
         }
     }
 
+    #[cfg(windows)]
     fn format (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
     {
         match crate::format_error (self.code) {
@@ -100,6 +152,12 @@ impl Error {
             None => write!(f, "unknown error code {}", self.code)
         }
     }
+
+    #[cfg(not(windows))]
+    fn format (&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "error code {}", self.code)
+    }
 }
 
 impl std::error::Error for Error {}
@@ -119,38 +177,97 @@ impl fmt::Display for Error {
 
 
 
+#[cfg(windows)]
 fn osstr_to_wchars (os_str: &OsStr) -> Vec<u16> {
     use std::iter::once;
     os_str.encode_wide ().chain (once (0)).collect ()
 }
 
+#[cfg(windows)]
 fn to_wstr(input: impl AsRef<OsStr>) -> Vec<u16>
 {
     osstr_to_wchars(input.as_ref())
 }
 
 pub mod registry;
+#[cfg(windows)]
 pub mod service_configuration {
-    use super::{ Result, registry::{KeyExt, HKEY_LOCAL_MACHINE} };
+    use super::{ Result, registry::{KeyExt, HKEY_LOCAL_MACHINE}, service_name::ServiceName };
 
-    fn get_service_key_path(name: &str) -> String {
+    fn get_service_key_path(name: &ServiceName) -> String {
         format!("SYSTEM\\CurrentControlSet\\Services\\{}", name)
     }
 
+    /// set the environment variables the service control manager adds to the
+    /// environment of the service process, replacing any set before
+    pub fn set_environment<K: AsRef<str>, V: AsRef<str>>(name: &ServiceName, variables: &[(K,V)]) -> Result<()> {
+        let value = variables.iter()
+            .map(|(key, value)|format!("{}={}", key.as_ref(), value.as_ref()))
+            .collect();
+        HKEY_LOCAL_MACHINE.create(get_service_key_path(name))?.set_value("Environment", &value)
+    }
+
+    /// save the configuration in one transaction, if saving fails part way
+    /// the previous configuration is left as it was
     #[cfg(feature = "serde_config")]
-    pub fn save<C>(name: &str, value: &C) -> Result<()> where C: serde::ser::Serialize {
-        HKEY_LOCAL_MACHINE.create(get_service_key_path(name))?.set_object("Configuration", value)
+    pub fn save<C>(name: &ServiceName, value: &C) -> Result<()> where C: serde::ser::Serialize {
+        HKEY_LOCAL_MACHINE.create(get_service_key_path(name))?.set_object_transacted("Configuration", value)
     }
 
     #[cfg(feature = "serde_config")]
-    pub fn load<C>(name: &str) -> Result<C> where C: serde::de::DeserializeOwned {
+    pub fn load<C>(name: &ServiceName) -> Result<C> where C: serde::de::DeserializeOwned {
         HKEY_LOCAL_MACHINE.open(get_service_key_path(name))?.query_object("Configuration")
     }
+
+    /// load the configuration and reload it each time it is changed in the
+    /// registry, e.g. by `save` or an administrator
+    #[cfg(all(feature = "serde_config", feature = "async_main"))]
+    pub fn watch<C>(name: &ServiceName) -> Result<super::registry::ObjectWatch<C>> where
+        C: serde::de::DeserializeOwned + Send + Sync + 'static,
+    {
+        HKEY_LOCAL_MACHINE.open(get_service_key_path(name))?.watch_object("Configuration")
+    }
+
+    /// load the configuration saved for the service on another machine
+    #[cfg(feature = "serde_config")]
+    pub fn load_remote<C>(machine: &str, name: &ServiceName) -> Result<C> where C: serde::de::DeserializeOwned {
+        super::registry::Key::connect_remote(machine, &HKEY_LOCAL_MACHINE)?.open(get_service_key_path(name))?.query_object("Configuration")
+    }
+
+    /// when the stored configuration was last written, only known for
+    /// configurations stored as a subkey, e.g. structs
+    pub fn last_changed(name: &ServiceName) -> Result<std::time::SystemTime> {
+        let key = HKEY_LOCAL_MACHINE.open(get_service_key_path(name))?.open("Configuration")?;
+        Ok(key.info()?.last_write_time)
+    }
+
+    /// delete the stored configuration, it is not an error if there is none
+    pub fn purge(name: &ServiceName) -> Result<()> {
+        use winapi::shared::winerror::ERROR_FILE_NOT_FOUND;
+
+        use super::registry::{OpenOptions, KeyAccess};
+
+        let key = match OpenOptions::new()
+            .access(KeyAccess::READ | KeyAccess::WRITE | KeyAccess::DELETE)
+            .open(&HKEY_LOCAL_MACHINE, get_service_key_path(name))
+        {
+            Err(error) if error.code == ERROR_FILE_NOT_FOUND => return Ok(()),
+            result => result?,
+        };
+        for result in [key.delete_value("Configuration"), key.delete_tree("Configuration")] {
+            match result {
+                Err(error) if error.code != ERROR_FILE_NOT_FOUND => return Err(error),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
 }
 
 
 
 
+#[cfg(windows)]
 pub fn get_this_module_filename_raw() -> Result<Vec<u16>> {
     let mut buffer = Vec::<u16>::new();
 
@@ -166,6 +283,7 @@ pub fn get_this_module_filename_raw() -> Result<Vec<u16>> {
     }
 }
 
+#[cfg(windows)]
 pub fn get_this_module_filename() -> Result<PathBuf> {
     get_this_module_filename_raw().map(|buffer|PathBuf::from(OsString::from_wide(&buffer)))
 }
@@ -174,6 +292,7 @@ pub fn get_this_module_filename() -> Result<PathBuf> {
 
 
 /// Format a Win32 error code into a descriptive message.
+#[cfg(windows)]
 pub fn format_error(code: u32) -> Option<String> {
 
     use winapi::um::{
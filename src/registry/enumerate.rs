@@ -0,0 +1,152 @@
+//! Enumeration of subkeys and values with buffers that grow as needed
+//!
+//! `RegEnumKeyExW` and `RegEnumValueW` fail with `ERROR_MORE_DATA` when a
+//! buffer is too small, and do not reliably report the size they needed. The
+//! name buffers are doubled until the item fits, up to the registry limit of
+//! 16383 characters for names plus room for the NUL. The data buffer is
+//! resized to the reported length. The same code drives the windows registry
+//! and `MemoryKey`.
+
+use std::time::SystemTime;
+
+use super::{ValueBuf, system_time_from_filetime, ERROR_MORE_DATA, ERROR_NO_MORE_ITEMS};
+use crate::{Error, Result};
+
+/// no name or class is longer than this, including the NUL
+const MAX_BUFFER_LEN: usize = 32768;
+
+/// the primitives of an enumeration, with the contract of the win32
+/// functions: lengths returned exclude the NUL, which the buffers must also
+/// hold, and a buffer that is too small fails with `ERROR_MORE_DATA`
+pub(crate) trait RawEnum {
+    /// the name length, the class length and the last write time as a
+    /// `FILETIME`
+    fn enum_key(&self, index: u32, name: &mut [u16], class: &mut [u16]) -> Result<(usize,usize,u64)>;
+
+    /// the name length and the value type, `data_len` receives the length of
+    /// the data even when `data` is too small or not given
+    fn enum_value(&self, index: u32, name: &mut [u16], data: Option<&mut [u8]>, data_len: &mut usize) -> Result<(usize,u32)>;
+}
+
+/// a subkey found by `iter_subkeys`
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub struct Subkey<N> {
+    pub name: N,
+    /// usually empty
+    pub class: N,
+    pub last_write_time: SystemTime,
+}
+
+fn is_more_data(result: &Result<impl Sized>) -> bool {
+    matches!(result, Err(Error{code: ERROR_MORE_DATA}))
+}
+
+fn grow(buffer: &mut Vec<u16>) -> bool {
+    if buffer.len() >= MAX_BUFFER_LEN {
+        false
+    } else {
+        let len = (buffer.len() * 2).clamp(256, MAX_BUFFER_LEN);
+        buffer.resize(len, 0);
+        true
+    }
+}
+
+/// the buffers of an enumeration, kept between steps
+#[derive(Default)]
+pub(crate) struct Buffers {
+    name: Vec<u16>,
+    class: Vec<u16>,
+    data: Vec<u8>,
+}
+
+impl Buffers {
+    /// start with buffers large enough for the lengths `info` reported, which
+    /// saves growing them unless the key changes while enumerating
+    #[cfg(windows)]
+    pub(crate) fn with_lengths(name_len: u32, class_len: u32, data_len: u32) -> Self {
+        Self{
+            name: vec![0; name_len as usize + 1],
+            class: vec![0; class_len as usize + 1],
+            data: vec![0; data_len as usize],
+        }
+    }
+
+    pub(crate) fn subkey(&mut self, key: &impl RawEnum, index: u32) -> Result<Subkey<Vec<u16>>> {
+        if self.name.is_empty() {
+            grow(&mut self.name);
+        }
+        if self.class.is_empty() {
+            grow(&mut self.class);
+        }
+        loop {
+            let result = key.enum_key(index, &mut self.name, &mut self.class);
+            if is_more_data(&result) && (grow(&mut self.name) | grow(&mut self.class)) {
+                continue;
+            }
+            let (name_len, class_len, last_write_time) = result?;
+            break Ok(Subkey{
+                name: self.name[..name_len].to_vec(),
+                class: self.class[..class_len].to_vec(),
+                last_write_time: system_time_from_filetime(last_write_time),
+            });
+        }
+    }
+
+    pub(crate) fn value(&mut self, key: &impl RawEnum, index: u32) -> Result<(Vec<u16>,ValueBuf)> {
+        if self.name.is_empty() {
+            grow(&mut self.name);
+        }
+        loop {
+            let mut data_len = 0;
+            let result = key.enum_value(index, &mut self.name, Some(&mut self.data), &mut data_len);
+            if data_len > self.data.len() && (result.is_ok() || is_more_data(&result)) {
+                self.data.resize(data_len, 0);
+                continue;
+            }
+            if is_more_data(&result) && grow(&mut self.name) {
+                continue;
+            }
+            let (name_len, value_type) = result?;
+            break Ok((self.name[..name_len].to_vec(), ValueBuf::decode(value_type, &self.data[..data_len])?));
+        }
+    }
+
+    pub(crate) fn value_name(&mut self, key: &impl RawEnum, index: u32) -> Result<Vec<u16>> {
+        if self.name.is_empty() {
+            grow(&mut self.name);
+        }
+        loop {
+            let result = key.enum_value(index, &mut self.name, None, &mut 0);
+            if is_more_data(&result) && grow(&mut self.name) {
+                continue;
+            }
+            let (name_len, _) = result?;
+            break Ok(self.name[..name_len].to_vec());
+        }
+    }
+}
+
+/// the position in an enumeration of `key`
+pub(crate) struct Enumeration<K> {
+    pub(crate) key: K,
+    index: u32,
+    buffers: Buffers,
+}
+
+impl<K: RawEnum> Enumeration<K> {
+    pub(crate) fn new(key: K, buffers: Buffers) -> Self {
+        Self{ key, index: 0, buffers }
+    }
+
+    /// take the next item, ending at `ERROR_NO_MORE_ITEMS`, an item that
+    /// fails is skipped after reporting the error
+    pub(crate) fn next<T>(&mut self, step: impl FnOnce(&mut Buffers,&K,u32)->Result<T>) -> Option<Result<T>> {
+        match step(&mut self.buffers, &self.key, self.index) {
+            Err(Error{code: ERROR_NO_MORE_ITEMS}) => None,
+            result => {
+                self.index += 1;
+                Some(result)
+            },
+        }
+    }
+}
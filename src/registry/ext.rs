@@ -4,6 +4,7 @@ use std::{
     marker::PhantomData,
     ffi::OsString,
     mem::MaybeUninit,
+    time::UNIX_EPOCH,
     os::windows::ffi::{
         //OsStrExt,
         OsStringExt,
@@ -12,24 +13,33 @@ use std::{
 
 use winapi::{
     shared::{
-        minwindef::HKEY,
-        winerror::{
-            ERROR_NO_MORE_ITEMS,
-        },
+        minwindef::{HKEY,FILETIME},
     },
     um::{
+        winnt::{
+            DELETE,
+            KEY_ENUMERATE_SUB_KEYS,
+            KEY_QUERY_VALUE,
+            KEY_SET_VALUE,
+        },
         winreg::{
+            RegOpenKeyExW,
+            RegCloseKey,
+            RegDeleteValueW,
+            RegDeleteKeyW,
+            RegDeleteTreeW,
             RegQueryValueExW,
             RegSetValueExW,
             RegEnumValueW,
-            RegEnumKeyW,
+            RegEnumKeyExW,
+            RegQueryInfoKeyW,
         }
     },
 };
 
 use super::{
-    Key, Name, NameBuf, ValueBuf,
-    MaybeUninitValue,
+    Key, KeyRef, OpenOptions, KeyAccess, Transaction, Name, NameBuf, ValueBuf, KeyInfo, Subkey, system_time_from_filetime,
+    enumerate::{RawEnum, Buffers, Enumeration},
     Error, Result, to_wstr,
 };
 
@@ -46,20 +56,54 @@ pub trait KeyExt {
     }
 
     fn set_value(&self, name: impl AsRef<Name>, value: &ValueBuf) -> Result<()> {
-        unsafe { raw_set_value(self.handle(), name, value.type_ptr_len()?) }
+        let data = value.encode();
+        unsafe { raw_set_value(self.handle(), name, (value.value_type(), (data.as_ptr(), data.len() as u32))) }
     }
 
     fn query_value(&self, name: impl AsRef<Name>) -> Result<ValueBuf> {
         unsafe {
             let name = name.as_ref();
 
-            let (value_type, value_len) = raw_query_value(self.handle(), name, (null_mut(), 0))?;
+            let (_, value_len) = raw_query_value(self.handle(), name, (null_mut(), 0))?;
 
-            let mut value = MaybeUninitValue::uninit(value_type, value_len)?;
+            let mut data = vec![0u8; value_len as usize];
 
-            let (value_type, _) = raw_query_value(self.handle(), name, value.ptr_and_len()?)?;
+            let (value_type, value_len) = raw_query_value(self.handle(), name, (data.as_mut_ptr(), data.len() as u32))?;
 
-            value.assume_init(value_type)
+            ValueBuf::decode(value_type, &data[..value_len as usize])
+        }
+    }
+
+    /// delete a value, the key must have been opened for writing
+    fn delete_value(&self, name: impl AsRef<Name>) -> Result<()> {
+        let value_name = to_wstr(name);
+        Error::check_code(unsafe { RegDeleteValueW(self.handle(), value_name.as_ptr()) })
+    }
+
+    /// delete a subkey, it must not have subkeys of its own
+    fn delete_key(&self, path: impl AsRef<Name>) -> Result<()> {
+        let path = to_wstr(path);
+        Error::check_code(unsafe { RegDeleteKeyW(self.handle(), path.as_ptr()) })
+    }
+
+    /// delete a subkey with all of its values and subkeys
+    fn delete_tree(&self, path: impl AsRef<Name>) -> Result<()> {
+        let path = to_wstr(path);
+        unsafe {
+            // RegDeleteTreeW needs these rights on the key it empties
+            let mut subkey = MaybeUninit::<HKEY>::uninit();
+            Error::check_code(RegOpenKeyExW(
+                /* hKey       */ self.handle(),
+                /* lpSubKey   */ path.as_ptr(),
+                /* ulOptions  */ 0u32,
+                /* samDesired */ DELETE | KEY_ENUMERATE_SUB_KEYS | KEY_QUERY_VALUE | KEY_SET_VALUE,
+                /* phkResult  */ subkey.as_mut_ptr(),
+            ))?;
+            let subkey = subkey.assume_init();
+            let emptied = Error::check_code(RegDeleteTreeW(subkey, null_mut()));
+            RegCloseKey(subkey);
+            emptied?;
+            Error::check_code(RegDeleteKeyW(self.handle(), path.as_ptr()))
         }
     }
 
@@ -74,6 +118,20 @@ pub trait KeyExt {
         value.serialize(&mut ser)
     }
 
+    /// like `set_object`, but if it fails part way through nothing is
+    /// changed
+    #[cfg(feature = "serde_config")]
+    fn set_object_transacted(&self, name: impl Into<String>, value: &impl Serialize) -> Result<()> where Self: Sized {
+        let transaction = Transaction::new()?;
+        let key = OpenOptions::new()
+            .access(KeyAccess::READ | KeyAccess::WRITE)
+            .open_transacted(self, "", &transaction)?;
+        key.set_object(name, value)?;
+        // the handles must be closed before committing
+        drop(key);
+        transaction.commit()
+    }
+
     #[cfg(feature = "serde_config")]
     fn query_object<V>(&self, name: impl Into<String>) -> Result<V> where V: serde::de::DeserializeOwned {
         use crate::serde_config::Deserializer;
@@ -91,23 +149,76 @@ pub trait KeyExt {
         V::deserialize(&mut des)
     }
 
+    /// counts, maximum name and data lengths and the last write time
+    fn info(&self) -> Result<KeyInfo> {
+        unsafe { raw_query_info(self.handle()) }
+    }
+
     fn iter_values(&self) -> ValueIterator {
-        ValueIterator(unsafe {self.handle()}, 0, Default::default())
+        ValueIterator(enumeration(self, |info|Buffers::with_lengths(info.max_value_name_len, 0, info.max_value_len)))
     }
 
     fn iter_key_names(&self) -> KeyNameIterator {
-        KeyNameIterator(unsafe {self.handle()}, 0, Default::default())
+        KeyNameIterator(enumeration(self, |info|Buffers::with_lengths(info.max_subkey_name_len, info.max_class_len, 0)))
+    }
+
+    /// the subkeys with their class names and last write times
+    fn iter_subkeys(&self) -> SubkeyIterator {
+        SubkeyIterator(enumeration(self, |info|Buffers::with_lengths(info.max_subkey_name_len, info.max_class_len, 0)))
     }
 
     fn iter_value_names(&self) -> ValueNameIterator {
-        ValueNameIterator(unsafe {self.handle()}, 0, Default::default())
+        ValueNameIterator(enumeration(self, |info|Buffers::with_lengths(info.max_value_name_len, 0, 0)))
     }
+
+}
+
+fn enumeration<K: KeyExt + ?Sized>(key: &K, buffers: impl FnOnce(&KeyInfo)->Buffers) -> Enumeration<KeyRef<'_>> {
+    // the buffers grow if needed, sizing them up front only saves retries
+    let buffers = key.info().map(|info|buffers(&info)).unwrap_or_default();
+    Enumeration::new(KeyRef(unsafe {key.handle()}, PhantomData), buffers)
 }
 
-pub struct ValueIterator<'a>(HKEY,u32,PhantomData<&'a Key>);
+pub struct ValueIterator<'a>(Enumeration<KeyRef<'a>>);
+
+pub struct KeyNameIterator<'a>(Enumeration<KeyRef<'a>>);
+pub struct ValueNameIterator<'a>(Enumeration<KeyRef<'a>>);
+pub struct SubkeyIterator<'a>(Enumeration<KeyRef<'a>>);
 
-pub struct KeyNameIterator<'a>(HKEY,u32,PhantomData<&'a Key>);
-pub struct ValueNameIterator<'a>(HKEY,u32,PhantomData<&'a Key>);
+fn filetime(time: &FILETIME) -> u64 {
+    (time.dwHighDateTime as u64) << 32 | time.dwLowDateTime as u64
+}
+
+unsafe fn raw_query_info(key: HKEY) -> Result<KeyInfo> {
+    let mut info = KeyInfo{
+        subkeys: 0,
+        max_subkey_name_len: 0,
+        max_class_len: 0,
+        values: 0,
+        max_value_name_len: 0,
+        max_value_len: 0,
+        security_descriptor_len: 0,
+        last_write_time: UNIX_EPOCH,
+    };
+    let mut last_write_time = MaybeUninit::<FILETIME>::uninit();
+    Error::check_code(RegQueryInfoKeyW(
+        /* hKey                   */ key,
+        /* lpClass                */ null_mut(),
+        /* lpcchClass             */ null_mut(),
+        /* lpReserved             */ null_mut(),
+        /* lpcSubKeys             */ &mut info.subkeys,
+        /* lpcbMaxSubKeyLen       */ &mut info.max_subkey_name_len,
+        /* lpcbMaxClassLen        */ &mut info.max_class_len,
+        /* lpcValues              */ &mut info.values,
+        /* lpcbMaxValueNameLen    */ &mut info.max_value_name_len,
+        /* lpcbMaxValueLen        */ &mut info.max_value_len,
+        /* lpcbSecurityDescriptor */ &mut info.security_descriptor_len,
+        /* lpftLastWriteTime      */ last_write_time.as_mut_ptr(),
+    ))?;
+    let last_write_time = last_write_time.assume_init();
+    info.last_write_time = system_time_from_filetime(filetime(&last_write_time));
+    Ok(info)
+}
 
 unsafe fn raw_query_value(key: HKEY, name: impl AsRef<Name>, (value_ptr, mut value_len): (*mut u8, u32)) -> Result<(u32,u32)> {
     let value_name: &[u16] = &to_wstr(name);
@@ -136,101 +247,59 @@ unsafe fn raw_set_value(key: HKEY, name: impl AsRef<Name>, (value_type, (value_p
     ))
 }
 
-fn extra_raw_enum_value(key: HKEY, index: u32, name: &mut [u16], (value_ptr, value_len): (*mut u8, u32)) -> Result<(u32,u32,u32)> {
-
-    let (name, mut name_len) = (name.as_mut_ptr(), name.len () as u32);
-    let mut value_type : u32 = 0;
-    let mut value_len = value_len;
-
-    Error::check_code(unsafe { RegEnumValueW(
-        /* hKey */ key,
-        /* dwIndex */ index,
-        /* lpValueName */ name,
-        /* lpcchValueName */ &mut name_len,
-        /* lpReserved */ null_mut(),
-        /* lpType */ &mut value_type,
-        /* lpData */ value_ptr,
-        /* lpcbData */ &mut value_len,
-    ) })?;
-
-    Ok((name_len, value_type, value_len))
-}
-
-fn raw_enum_value(key: HKEY, index: u32) -> Result<(NameBuf,ValueBuf)> {
-    unsafe {
-
-        let mut name_buffer : [u16;512] = std::mem::zeroed();
-
-        let (name_len, value_type, value_len) = extra_raw_enum_value(key, index, &mut name_buffer, (null_mut(), 0))?;
-
-        let name : &[u16] = &name_buffer[0..(name_len as usize)];
-        let name = Vec::from(name);
-
-        let mut value = MaybeUninitValue::uninit(value_type, value_len)?;
-
-        let (_, value_type, _) = extra_raw_enum_value(
-            key,
-            index,
-            &mut name_buffer,
-            value.ptr_and_len()?
-        )?;
-
-        let name = OsString::from_wide (std::slice::from_raw_parts (name.as_ptr(), name.len()));
-        let value = value.assume_init(value_type)?;
-
-        Ok((name, value))
+impl<'a> RawEnum for KeyRef<'a> {
+    fn enum_key(&self, index: u32, name: &mut [u16], class: &mut [u16]) -> Result<(usize,usize,u64)> {
+        let mut name_len = name.len() as u32;
+        let mut class_len = class.len() as u32;
+        let mut last_write_time = MaybeUninit::<FILETIME>::uninit();
+        unsafe {
+            Error::check_code(RegEnumKeyExW(
+                /* hKey              */ self.0,
+                /* dwIndex           */ index,
+                /* lpName            */ name.as_mut_ptr(),
+                /* lpcchName         */ &mut name_len,
+                /* lpReserved        */ null_mut(),
+                /* lpClass           */ class.as_mut_ptr(),
+                /* lpcchClass        */ &mut class_len,
+                /* lpftLastWriteTime */ last_write_time.as_mut_ptr(),
+            ))?;
+            let last_write_time = last_write_time.assume_init();
+            Ok((name_len as usize, class_len as usize, filetime(&last_write_time)))
+        }
     }
-}
-
-unsafe fn raw_enum_key(key: HKEY, index: u32, name: &mut [u16]) -> Result<u32> {
-
-    let name_len = name.len() as u32;
-
-    Error::check_code(RegEnumKeyW(
-        /* hKey    */ key,
-        /* dwIndex */ index,
-        /* lpName  */ name.as_mut_ptr(),
-        /* cchName */ name_len,
-    ))?;
-
-    Ok(match name.iter().position(|v|*v == 0) {
-        Some(index) => index,
-        None => name.len ()
-    } as u32)
-}
 
-fn name_buffer_to_string(buf: &[u16], len: u32) -> OsString {
-    OsString::from_wide (&buf[0..(len as usize)])
+    fn enum_value(&self, index: u32, name: &mut [u16], data: Option<&mut [u8]>, data_len: &mut usize) -> Result<(usize,u32)> {
+        let mut name_len = name.len() as u32;
+        let mut value_type = 0u32;
+        // a null data pointer asks for the length only
+        let (data_ptr, mut value_len) = match data {
+            Some(data) if !data.is_empty() => (data.as_mut_ptr(), data.len() as u32),
+            _ => (null_mut(), 0),
+        };
+        let result = Error::check_code(unsafe { RegEnumValueW(
+            /* hKey           */ self.0,
+            /* dwIndex        */ index,
+            /* lpValueName    */ name.as_mut_ptr(),
+            /* lpcchValueName */ &mut name_len,
+            /* lpReserved     */ null_mut(),
+            /* lpType         */ &mut value_type,
+            /* lpData         */ data_ptr,
+            /* lpcbData       */ &mut value_len,
+        ) });
+        *data_len = value_len as usize;
+        result.map(|_|(name_len as usize, value_type))
+    }
 }
 
-fn raw_enum_value_name(key: HKEY, index: u32) -> Result<NameBuf> {
-    unsafe {
-
-        let mut name_buf : [u16;512] = std::mem::zeroed();
-
-        let (name_len, _, _) = extra_raw_enum_value(key, index, &mut name_buf, (null_mut(), 0))?;
-
-        Ok(name_buffer_to_string(&mut name_buf, name_len))
-    }
+fn to_name(wide: Vec<u16>) -> NameBuf {
+    OsString::from_wide(&wide)
 }
 
 impl<'a> Iterator for ValueIterator<'a> {
     type Item=Result<(NameBuf,ValueBuf)>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match raw_enum_value(self.0, self.1) {
-            Ok(named_value) => {
-                self.1 += 1;
-                Some(Ok(named_value))
-            },
-            Err(error) => {
-                if error.code == ERROR_NO_MORE_ITEMS {
-                    None
-                } else {
-                    Some(Err(error))
-                }
-            }
-        }
+        self.0.next(|buffers, key, index|buffers.value(key, index).map(|(name, value)|(to_name(name), value)))
     }
 }
 
@@ -238,22 +307,19 @@ impl<'a> Iterator for KeyNameIterator<'a> {
     type Item=Result<NameBuf>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        unsafe {
-            let mut name_buf : [u16;512] = std::mem::zeroed();
-            match raw_enum_key(self.0, self.1, &mut name_buf) {
-                Ok(name_len) => {
-                    self.1 += 1;
-                    Some(Ok(name_buffer_to_string(&mut name_buf, name_len)))
-                },
-                Err(error) => {
-                    if error.code == ERROR_NO_MORE_ITEMS {
-                        None
-                    } else {
-                        Some(Err(error))
-                    }
-                }
-            }
-        }
+        self.0.next(|buffers, key, index|buffers.subkey(key, index).map(|subkey|to_name(subkey.name)))
+    }
+}
+
+impl<'a> Iterator for SubkeyIterator<'a> {
+    type Item=Result<Subkey<NameBuf>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next(|buffers, key, index|buffers.subkey(key, index).map(|subkey|Subkey{
+            name: to_name(subkey.name),
+            class: to_name(subkey.class),
+            last_write_time: subkey.last_write_time,
+        }))
     }
 }
 
@@ -261,18 +327,6 @@ impl<'a> Iterator for ValueNameIterator<'a> {
     type Item=Result<NameBuf>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match raw_enum_value_name(self.0, self.1) {
-            Ok(named_value) => {
-                self.1 += 1;
-                Some(Ok(named_value))
-            },
-            Err(error) => {
-                if error.code == ERROR_NO_MORE_ITEMS {
-                    None
-                } else {
-                    Some(Err(error))
-                }
-            }
-        }
+        self.0.next(|buffers, key, index|buffers.value_name(key, index).map(to_name))
     }
 }
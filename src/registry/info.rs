@@ -0,0 +1,61 @@
+//! Key metadata
+
+use std::time::{Duration,SystemTime,UNIX_EPOCH};
+
+/// 100ns intervals between 1601-01-01, the `FILETIME` epoch, and 1970-01-01
+const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+
+/// convert a `FILETIME`, 100ns intervals since 1601-01-01 UTC
+pub fn system_time_from_filetime(filetime: u64) -> SystemTime {
+    if filetime >= FILETIME_UNIX_EPOCH {
+        UNIX_EPOCH + filetime_duration(filetime - FILETIME_UNIX_EPOCH)
+    } else {
+        UNIX_EPOCH - filetime_duration(FILETIME_UNIX_EPOCH - filetime)
+    }
+}
+
+/// convert to a `FILETIME`, times before 1601 become 0
+pub fn filetime_from_system_time(time: SystemTime) -> u64 {
+    let intervals = |duration: Duration| duration.as_secs() * 10_000_000 + duration.subsec_nanos() as u64 / 100;
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => FILETIME_UNIX_EPOCH + intervals(after),
+        Err(before) => FILETIME_UNIX_EPOCH.saturating_sub(intervals(before.duration())),
+    }
+}
+
+fn filetime_duration(intervals: u64) -> Duration {
+    Duration::new(intervals / 10_000_000, (intervals % 10_000_000) as u32 * 100)
+}
+
+/// counts and sizes of what a key holds, lengths are in UTF-16 code units
+/// without the terminating NUL, except data lengths which are in bytes
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub struct KeyInfo {
+    pub subkeys: u32,
+    pub max_subkey_name_len: u32,
+    pub max_class_len: u32,
+    pub values: u32,
+    pub max_value_name_len: u32,
+    pub max_value_len: u32,
+    pub security_descriptor_len: u32,
+    /// when the key, or one of its values, was last written
+    pub last_write_time: SystemTime,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn filetimes() {
+        assert_eq!(system_time_from_filetime(FILETIME_UNIX_EPOCH), UNIX_EPOCH);
+        // 2021-01-01T00:00:00.5Z
+        assert_eq!(
+            system_time_from_filetime(132_539_328_005_000_000),
+            UNIX_EPOCH + Duration::from_millis(1_609_459_200_500),
+        );
+        assert_eq!(system_time_from_filetime(0), UNIX_EPOCH - Duration::from_secs(11_644_473_600));
+        assert_eq!(filetime_from_system_time(system_time_from_filetime(132_539_328_005_000_000)), 132_539_328_005_000_000);
+        assert_eq!(filetime_from_system_time(UNIX_EPOCH - Duration::from_secs(11_644_473_601)), 0);
+    }
+}
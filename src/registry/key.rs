@@ -2,7 +2,6 @@
 use std::{
     ptr::null_mut,
     mem::MaybeUninit,
-    ffi::OsStr,
 };
 
 use winapi::{
@@ -11,18 +10,34 @@ use winapi::{
     },
     um::{
         processthreadsapi::GetCurrentProcess,
-        handleapi::DuplicateHandle,
+        handleapi::{DuplicateHandle,CloseHandle,INVALID_HANDLE_VALUE},
+        ktmw32::{CreateTransaction,CommitTransaction,RollbackTransaction},
         winnt::{
             HANDLE,
+            DELETE,
+            KEY_QUERY_VALUE,
+            KEY_SET_VALUE,
+            KEY_CREATE_SUB_KEY,
+            KEY_ENUMERATE_SUB_KEYS,
+            KEY_NOTIFY,
+            KEY_CREATE_LINK,
             KEY_READ,
             KEY_WRITE,
+            KEY_ALL_ACCESS,
+            KEY_WOW64_32KEY,
+            KEY_WOW64_64KEY,
+            REG_OPTION_VOLATILE,
+            REG_CREATED_NEW_KEY,
             DUPLICATE_SAME_ACCESS,
         },
         winreg::{
             self,
             RegOpenKeyExW,
             RegCreateKeyExW,
+            RegOpenKeyTransactedW,
+            RegCreateKeyTransactedW,
             RegCloseKey,
+            RegConnectRegistryW,
         }
     },
 };
@@ -33,58 +48,279 @@ use super::{
 
 pub struct Key(pub(super) HKEY);
 
+pub const HKEY_CLASSES_ROOT: Key = Key(winreg::HKEY_CLASSES_ROOT);
 pub const HKEY_CURRENT_USER: Key = Key(winreg::HKEY_CURRENT_USER);
 pub const HKEY_LOCAL_MACHINE: Key = Key(winreg::HKEY_LOCAL_MACHINE);
+pub const HKEY_USERS: Key = Key(winreg::HKEY_USERS);
+pub const HKEY_PERFORMANCE_DATA: Key = Key(winreg::HKEY_PERFORMANCE_DATA);
+pub const HKEY_CURRENT_CONFIG: Key = Key(winreg::HKEY_CURRENT_CONFIG);
 
 impl KeyExt for Key {
     unsafe fn handle(&self) -> HKEY { self.0 }
 }
 
-impl Key {
+bit_flags!{
+    /// rights requested when opening a key
+    KeyAccess {
+        QUERY_VALUE = KEY_QUERY_VALUE,
+        SET_VALUE = KEY_SET_VALUE,
+        CREATE_SUB_KEY = KEY_CREATE_SUB_KEY,
+        ENUMERATE_SUB_KEYS = KEY_ENUMERATE_SUB_KEYS,
+        NOTIFY = KEY_NOTIFY,
+        CREATE_LINK = KEY_CREATE_LINK,
+        /// required by `delete_tree` on the key itself
+        DELETE = DELETE,
+        /// query and enumerate values and subkeys, and watch for changes
+        READ = KEY_READ,
+        /// set values and create subkeys
+        WRITE = KEY_WRITE,
+        ALL = KEY_ALL_ACCESS,
+    }
+}
 
-    /// open a key for writing
-    pub fn open(&self, path: impl AsRef<Name>) -> Result<Key> {
-        tracing::trace!("opening: {:?}", path.as_ref());
+/// which view of the registry a 32-bit process on 64-bit windows, or a
+/// 64-bit process, sees
+#[derive(Copy,Clone,Debug,Eq,PartialEq,Default)]
+pub enum View {
+    /// the view matching the bitness of this process
+    #[default]
+    Native,
+    /// the 32-bit view, `KEY_WOW64_32KEY`
+    Registry32,
+    /// the 64-bit view, `KEY_WOW64_64KEY`
+    Registry64,
+}
+
+impl View {
+    fn into_raw(self) -> u32 {
+        match self {
+            Self::Native => 0,
+            Self::Registry32 => KEY_WOW64_32KEY,
+            Self::Registry64 => KEY_WOW64_64KEY,
+        }
+    }
+}
+
+/// whether `OpenOptions::create` made a new key
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub enum Disposition {
+    CreatedNewKey,
+    OpenedExistingKey,
+}
+
+/// options for opening or creating keys, like `std::fs::OpenOptions`
+///
+/// ```ignore
+/// let (key, disposition) = OpenOptions::new()
+///     .access(KeyAccess::READ | KeyAccess::WRITE)
+///     .view(View::Registry64)
+///     .volatile(true)
+///     .create(&HKEY_CURRENT_USER, "SOFTWARE\\n8ware\\winsvc")?;
+/// ```
+#[derive(Clone,Debug)]
+pub struct OpenOptions {
+    access: KeyAccess,
+    view: View,
+    volatile: bool,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OpenOptions {
+    /// read access to the native view
+    pub fn new() -> Self {
+        Self{ access: KeyAccess::READ, view: View::Native, volatile: false }
+    }
+
+    pub fn access(&mut self, access: KeyAccess) -> &mut Self {
+        self.access = access;
+        self
+    }
+
+    pub fn view(&mut self, view: View) -> &mut Self {
+        self.view = view;
+        self
+    }
+
+    /// keys created are not saved and disappear on reboot, only used by
+    /// `create` and only when the key does not exist yet
+    pub fn volatile(&mut self, volatile: bool) -> &mut Self {
+        self.volatile = volatile;
+        self
+    }
+
+    fn sam_desired(&self) -> u32 {
+        self.access.into_raw() | self.view.into_raw()
+    }
+
+    /// open an existing key
+    pub fn open(&self, parent: &impl KeyExt, path: impl AsRef<Name>) -> Result<Key> {
+        self.raw_open(parent, path, null_mut())
+    }
+
+    /// open an existing key as part of `transaction`, as are the keys
+    /// opened or created through it
+    pub fn open_transacted(&self, parent: &impl KeyExt, path: impl AsRef<Name>, transaction: &Transaction) -> Result<Key> {
+        self.raw_open(parent, path, transaction.0)
+    }
+
+    /// create a new or open an existing key
+    pub fn create(&self, parent: &impl KeyExt, path: impl AsRef<Name>) -> Result<(Key,Disposition)> {
+        self.raw_create(parent, path, null_mut())
+    }
+
+    /// create a new or open an existing key as part of `transaction`, as
+    /// are the keys opened or created through it
+    pub fn create_transacted(&self, parent: &impl KeyExt, path: impl AsRef<Name>, transaction: &Transaction) -> Result<(Key,Disposition)> {
+        self.raw_create(parent, path, transaction.0)
+    }
+
+    fn raw_open(&self, parent: &impl KeyExt, path: impl AsRef<Name>, transaction: HANDLE) -> Result<Key> {
+        tracing::trace!("opening: {:?} with {:?}", path.as_ref(), self);
         unsafe {
             let path = to_wstr(path);
             let mut subkey = MaybeUninit::<HKEY>::uninit();
 
-            Error::check_code(
+            Error::check_code(if transaction.is_null() {
                 RegOpenKeyExW(
-                    /* hKey       */ self.0,
+                    /* hKey       */ parent.handle(),
                     /* lpSubKey   */ path.as_ptr(),
                     /* ulOptions  */ 0u32,
-                    /* samDesired */ KEY_READ,
+                    /* samDesired */ self.sam_desired(),
                     /* phkResult  */ subkey.as_mut_ptr()
                 )
-            )?;
+            } else {
+                RegOpenKeyTransactedW(
+                    /* hKey               */ parent.handle(),
+                    /* lpSubKey           */ path.as_ptr(),
+                    /* ulOptions          */ 0u32,
+                    /* samDesired         */ self.sam_desired(),
+                    /* phkResult          */ subkey.as_mut_ptr(),
+                    /* hTransaction       */ transaction,
+                    /* pExtendedParemeter */ null_mut(),
+                )
+            })?;
 
-            Ok(Self(subkey.assume_init()))
+            Ok(Key(subkey.assume_init()))
         }
     }
 
-    /// create a new or open an existing key for writing
-    pub fn create(&self, path: impl AsRef<OsStr>) -> Result<Key> {
-        tracing::trace!("creating: {:?}", path.as_ref());
+    fn raw_create(&self, parent: &impl KeyExt, path: impl AsRef<Name>, transaction: HANDLE) -> Result<(Key,Disposition)> {
+        tracing::trace!("creating: {:?} with {:?}", path.as_ref(), self);
         unsafe {
             let path = to_wstr(path);
             let mut subkey = MaybeUninit::<HKEY>::uninit();
+            let mut disposition = 0u32;
+            let options = if self.volatile { REG_OPTION_VOLATILE } else { 0u32 };
 
-            Error::check_code(
+            Error::check_code(if transaction.is_null() {
                 RegCreateKeyExW(
-                    /* hKey                 */ self.0,
+                    /* hKey                 */ parent.handle(),
+                    /* lpSubKey             */ path.as_ptr(),
+                    /* Reserved             */ 0u32,
+                    /* lpClass              */ null_mut(),
+                    /* dwOptions            */ options,
+                    /* samDesired           */ self.sam_desired(),
+                    /* lpSecurityAttributes */ null_mut(),
+                    /* phkResult            */ subkey.as_mut_ptr(),
+                    /* lpdwDisposition      */ &mut disposition,
+                )
+            } else {
+                RegCreateKeyTransactedW(
+                    /* hKey                 */ parent.handle(),
                     /* lpSubKey             */ path.as_ptr(),
                     /* Reserved             */ 0u32,
                     /* lpClass              */ null_mut(),
-                    /* dwOptions            */ 0u32,
-                    /* samDesired           */ KEY_WRITE,
+                    /* dwOptions            */ options,
+                    /* samDesired           */ self.sam_desired(),
                     /* lpSecurityAttributes */ null_mut(),
                     /* phkResult            */ subkey.as_mut_ptr(),
-                    /* lpdwDisposition      */ null_mut(),
+                    /* lpdwDisposition      */ &mut disposition,
+                    /* hTransaction         */ transaction,
+                    /* pExtendedParemeter   */ null_mut(),
+                )
+            })?;
+
+            let disposition = if disposition == REG_CREATED_NEW_KEY {
+                Disposition::CreatedNewKey
+            } else {
+                Disposition::OpenedExistingKey
+            };
+
+            Ok((Key(subkey.assume_init()), disposition))
+        }
+    }
+}
+
+/// a kernel transaction manager transaction, registry changes made through
+/// keys opened with it take effect together on `commit` or not at all
+///
+/// Dropping a transaction that was not committed rolls it back.
+pub struct Transaction(HANDLE);
+
+impl Transaction {
+    pub fn new() -> Result<Self> {
+        let handle = unsafe { CreateTransaction(null_mut(), null_mut(), 0, 0, 0, 0, null_mut()) };
+        if handle == INVALID_HANDLE_VALUE {
+            Err(Error::from_last())
+        } else {
+            Ok(Self(handle))
+        }
+    }
+
+    /// make every change take effect, keys opened with the transaction
+    /// should be dropped first
+    pub fn commit(self) -> Result<()> {
+        Error::check_true(unsafe { CommitTransaction(self.0) })
+    }
+
+    /// discard every change, the same as dropping the transaction
+    pub fn rollback(self) -> Result<()> {
+        Error::check_true(unsafe { RollbackTransaction(self.0) })
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        // closing a transaction that is not committed rolls it back
+        unsafe { CloseHandle(self.0); }
+    }
+}
+
+impl Key {
+
+    /// open a key for reading
+    pub fn open(&self, path: impl AsRef<Name>) -> Result<Key> {
+        OpenOptions::new().open(self, path)
+    }
+
+    /// create a new or open an existing key for writing
+    pub fn create(&self, path: impl AsRef<Name>) -> Result<Key> {
+        OpenOptions::new().access(KeyAccess::WRITE).create(self, path).map(|(key, _)|key)
+    }
+
+    /// connect to `HKEY_LOCAL_MACHINE`, `HKEY_USERS` or
+    /// `HKEY_PERFORMANCE_DATA` on another machine, which must run the
+    /// remote registry service
+    pub fn connect_remote(machine: &str, hive: &Key) -> Result<Key> {
+        tracing::trace!("connecting: {:?}", machine);
+        unsafe {
+            let machine = to_wstr(machine);
+            let mut key = MaybeUninit::<HKEY>::uninit();
+
+            Error::check_code(
+                RegConnectRegistryW(
+                    /* lpMachineName */ machine.as_ptr(),
+                    /* hKey          */ hive.0,
+                    /* phkResult     */ key.as_mut_ptr(),
                 )
             )?;
 
-            Ok(Self(subkey.assume_init()))
+            Ok(Key(key.assume_init()))
         }
     }
 
@@ -111,3 +347,60 @@ impl Drop for Key {
         unsafe { RegCloseKey(self.0); }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::registry::ValueBuf;
+
+    #[test]
+    fn create_volatile() {
+        let parent = HKEY_CURRENT_USER.create("SOFTWARE\\n8ware\\test\\winsvc").unwrap();
+        let _ = parent.delete_tree("volatile");
+
+        let mut options = OpenOptions::new();
+        options.access(KeyAccess::READ | KeyAccess::WRITE).volatile(true);
+
+        let (_, disposition) = options.create(&parent, "volatile").unwrap();
+        assert_eq!(disposition, Disposition::CreatedNewKey);
+
+        let (key, disposition) = options.create(&parent, "volatile").unwrap();
+        assert_eq!(disposition, Disposition::OpenedExistingKey);
+        key.set_value("value", &ValueBuf::Dword(1)).unwrap();
+        assert_eq!(key.query_value("value").unwrap(), ValueBuf::Dword(1));
+    }
+
+    #[cfg(feature = "serde_config")]
+    #[test]
+    fn object_transacted() {
+        use serde::{Serialize, Serializer, Deserialize, ser::Error as _};
+
+        #[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+        struct Config {
+            port: u32,
+            name: String,
+        }
+
+        /// fails once `port` has been written
+        #[derive(Serialize)]
+        struct Broken {
+            port: u32,
+            name: Failing,
+        }
+
+        struct Failing;
+
+        impl Serialize for Failing {
+            fn serialize<S: Serializer>(&self, _: S) -> std::result::Result<S::Ok, S::Error> {
+                Err(S::Error::custom("failing"))
+            }
+        }
+
+        let parent = HKEY_CURRENT_USER.create("SOFTWARE\\n8ware\\test\\winsvc").unwrap();
+        let _ = parent.delete_tree("transacted");
+        parent.set_object_transacted("transacted", &Config{ port: 80, name: "old".into() }).unwrap();
+
+        assert!(parent.set_object_transacted("transacted", &Broken{ port: 8080, name: Failing }).is_err());
+        assert_eq!(parent.query_object::<Config>("transacted").unwrap(), Config{ port: 80, name: "old".into() });
+    }
+}
@@ -0,0 +1,464 @@
+//! An in-memory registry
+//!
+//! `MemoryKey` behaves like an open key of the windows registry: names are
+//! compared without regard to case, subkeys enumerate in sorted order and
+//! values in the order they were first set, and the same error codes are
+//! returned. It backs tests on every platform and holds documents that are
+//! not in the registry, e.g. a parsed `.reg` file.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc,Mutex},
+    time::SystemTime,
+};
+
+use super::{
+    ValueBuf, KeyInfo, Subkey, filetime_from_system_time,
+    enumerate::{RawEnum, Buffers, Enumeration},
+    ERROR_FILE_NOT_FOUND, ERROR_ACCESS_DENIED, ERROR_MORE_DATA, ERROR_NO_MORE_ITEMS,
+};
+use crate::{Error, Result};
+
+fn fold(name: &str) -> String {
+    name.to_uppercase()
+}
+
+#[derive(Default)]
+struct Node {
+    class: String,
+    last_write_time: Option<SystemTime>,
+    values: Vec<(String,ValueBuf)>,
+    /// by folded name
+    subkeys: BTreeMap<String,(String,MemoryKey)>,
+}
+
+impl Node {
+    /// a copy of this node and every node below it
+    fn deep_clone(&self) -> Node {
+        Node{
+            class: self.class.clone(),
+            last_write_time: self.last_write_time,
+            values: self.values.clone(),
+            subkeys: self.subkeys.iter()
+                .map(|(folded, (name, subkey))|(folded.clone(), (name.clone(), MemoryKey::from(subkey.0.lock().unwrap().deep_clone()))))
+                .collect(),
+        }
+    }
+
+    fn touch(&mut self) {
+        self.last_write_time = Some(SystemTime::now());
+    }
+
+    fn value_index(&self, name: &str) -> Option<usize> {
+        let name = fold(name);
+        self.values.iter().position(|(value_name, _)|fold(value_name) == name)
+    }
+}
+
+/// a key of an in-memory registry, clones refer to the same key
+#[derive(Clone,Default)]
+pub struct MemoryKey(Arc<Mutex<Node>>);
+
+impl From<Node> for MemoryKey {
+    fn from(node: Node) -> Self {
+        Self(Arc::new(Mutex::new(node)))
+    }
+}
+
+impl std::fmt::Debug for MemoryKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let node = self.0.lock().unwrap();
+        f.debug_struct("MemoryKey")
+            .field("values", &node.values)
+            .field("subkeys", &node.subkeys.values().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// split a path on `\`, ignoring empty components
+fn components(path: &str) -> impl Iterator<Item=&str> {
+    path.split('\\').filter(|name|!name.is_empty())
+}
+
+/// copy `name` into `buffer` with a NUL, as the win32 functions do
+fn copy_name(name: &str, buffer: &mut [u16]) -> Result<usize> {
+    let wide: Vec<u16> = name.encode_utf16().collect();
+    if wide.len() < buffer.len() {
+        buffer[..wide.len()].copy_from_slice(&wide);
+        buffer[wide.len()] = 0;
+        Ok(wide.len())
+    } else {
+        Err(Error{code:ERROR_MORE_DATA})
+    }
+}
+
+impl MemoryKey {
+    /// an empty root key
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// open an existing subkey
+    pub fn open(&self, path: &str) -> Result<MemoryKey> {
+        let mut key = self.clone();
+        for name in components(path) {
+            let subkey = key.0.lock().unwrap().subkeys.get(&fold(name)).map(|(_, subkey)|subkey.clone());
+            key = subkey.ok_or(Error{code:ERROR_FILE_NOT_FOUND})?;
+        }
+        Ok(key)
+    }
+
+    /// create a new or open an existing subkey
+    pub fn create(&self, path: &str) -> Result<MemoryKey> {
+        let mut key = self.clone();
+        for name in components(path) {
+            let subkey = {
+                let mut node = key.0.lock().unwrap();
+                if !node.subkeys.contains_key(&fold(name)) {
+                    node.touch();
+                    let subkey = MemoryKey::new();
+                    subkey.0.lock().unwrap().touch();
+                    node.subkeys.insert(fold(name), (name.into(), subkey));
+                }
+                node.subkeys[&fold(name)].1.clone()
+            };
+            key = subkey;
+        }
+        Ok(key)
+    }
+
+    /// the class name given to a key when it was created, usually empty
+    pub fn set_class(&self, class: &str) {
+        self.0.lock().unwrap().class = class.into();
+    }
+
+    pub fn set_value(&self, name: &str, value: &ValueBuf) -> Result<()> {
+        let mut node = self.0.lock().unwrap();
+        node.touch();
+        match node.value_index(name) {
+            Some(index) => node.values[index].1 = value.clone(),
+            None => node.values.push((name.into(), value.clone())),
+        }
+        Ok(())
+    }
+
+    pub fn query_value(&self, name: &str) -> Result<ValueBuf> {
+        let node = self.0.lock().unwrap();
+        match node.value_index(name) {
+            Some(index) => Ok(node.values[index].1.clone()),
+            None => Err(Error{code:ERROR_FILE_NOT_FOUND}),
+        }
+    }
+
+    pub fn delete_value(&self, name: &str) -> Result<()> {
+        let mut node = self.0.lock().unwrap();
+        let index = node.value_index(name).ok_or(Error{code:ERROR_FILE_NOT_FOUND})?;
+        node.values.remove(index);
+        node.touch();
+        Ok(())
+    }
+
+    /// remove the last component of `path` from its parent, `check` may
+    /// refuse
+    fn remove(&self, path: &str, check: impl FnOnce(&MemoryKey)->Result<()>) -> Result<()> {
+        let path = path.trim_end_matches('\\');
+        let (parent, name) = match path.rsplit_once('\\') {
+            Some((parent, name)) => (self.open(parent)?, name),
+            None => (self.clone(), path),
+        };
+        let mut node = parent.0.lock().unwrap();
+        let (_, subkey) = node.subkeys.get(&fold(name)).ok_or(Error{code:ERROR_FILE_NOT_FOUND})?;
+        check(subkey)?;
+        node.subkeys.remove(&fold(name));
+        node.touch();
+        Ok(())
+    }
+
+    /// delete a subkey, it must not have subkeys of its own
+    pub fn delete_key(&self, path: &str) -> Result<()> {
+        self.remove(path, |subkey| {
+            if subkey.0.lock().unwrap().subkeys.is_empty() {
+                Ok(())
+            } else {
+                Err(Error{code:ERROR_ACCESS_DENIED})
+            }
+        })
+    }
+
+    /// delete a subkey with all of its values and subkeys
+    pub fn delete_tree(&self, path: &str) -> Result<()> {
+        self.remove(path, |_|Ok(()))
+    }
+
+    pub fn info(&self) -> KeyInfo {
+        let node = self.0.lock().unwrap();
+        let len = |name: &str| name.encode_utf16().count() as u32;
+        let subkeys = node.subkeys.values();
+        KeyInfo{
+            subkeys: node.subkeys.len() as u32,
+            max_subkey_name_len: subkeys.clone().map(|(name, _)|len(name)).max().unwrap_or(0),
+            max_class_len: subkeys.map(|(_, subkey)|len(&subkey.0.lock().unwrap().class)).max().unwrap_or(0),
+            values: node.values.len() as u32,
+            max_value_name_len: node.values.iter().map(|(name, _)|len(name)).max().unwrap_or(0),
+            max_value_len: node.values.iter().map(|(_, value)|value.encode().len() as u32).max().unwrap_or(0),
+            security_descriptor_len: 0,
+            last_write_time: node.last_write_time.unwrap_or(std::time::UNIX_EPOCH),
+        }
+    }
+
+    /// stage changes to this key and its subkeys in a copy, they replace the
+    /// key only on `commit`
+    ///
+    /// This stands in for the registry transactions of `Transaction`, but
+    /// without isolation, changes made to the key directly while staging are
+    /// lost on commit. Keys opened below this key before the commit no longer
+    /// refer to it afterwards.
+    pub fn stage(&self) -> StagedKey {
+        let staged = MemoryKey::from(self.0.lock().unwrap().deep_clone());
+        StagedKey{ target: self.clone(), staged }
+    }
+
+    /// store `value` under `name` as `KeyExt::set_object` does
+    #[cfg(feature = "serde_config")]
+    pub fn set_object(&self, name: impl Into<String>, value: &impl serde::Serialize) -> Result<()> {
+        value.serialize(&mut crate::serde_config::Serializer::new(self.clone(), name.into()))
+    }
+
+    /// load the object stored under `name` as `KeyExt::query_object` does
+    #[cfg(feature = "serde_config")]
+    pub fn query_object<V>(&self, name: impl Into<String>) -> Result<V> where V: serde::de::DeserializeOwned {
+        V::deserialize(&mut crate::serde_config::Deserializer::new(self.clone(), name.into()))
+    }
+
+    // the buffers start empty, so enumerating grows them as the windows
+    // registry may when a key changes while it is enumerated
+
+    pub fn iter_values(&self) -> MemoryValueIterator {
+        MemoryValueIterator(Enumeration::new(self.clone(), Buffers::default()))
+    }
+
+    pub fn iter_value_names(&self) -> MemoryValueNameIterator {
+        MemoryValueNameIterator(Enumeration::new(self.clone(), Buffers::default()))
+    }
+
+    pub fn iter_key_names(&self) -> MemoryKeyNameIterator {
+        MemoryKeyNameIterator(Enumeration::new(self.clone(), Buffers::default()))
+    }
+
+    pub fn iter_subkeys(&self) -> MemorySubkeyIterator {
+        MemorySubkeyIterator(Enumeration::new(self.clone(), Buffers::default()))
+    }
+}
+
+/// changes staged by `MemoryKey::stage`, discarded if dropped without a
+/// `commit`
+pub struct StagedKey {
+    target: MemoryKey,
+    staged: MemoryKey,
+}
+
+impl StagedKey {
+    /// the key to make the changes to
+    pub fn key(&self) -> &MemoryKey {
+        &self.staged
+    }
+
+    /// replace the key with the staged copy
+    pub fn commit(self) {
+        let staged = std::mem::take(&mut *self.staged.0.lock().unwrap());
+        *self.target.0.lock().unwrap() = staged;
+    }
+}
+
+impl RawEnum for MemoryKey {
+    fn enum_key(&self, index: u32, name: &mut [u16], class: &mut [u16]) -> Result<(usize,usize,u64)> {
+        let node = self.0.lock().unwrap();
+        let (subkey_name, subkey) = node.subkeys.values().nth(index as usize).ok_or(Error{code:ERROR_NO_MORE_ITEMS})?;
+        let subkey = subkey.0.lock().unwrap();
+        let name_len = copy_name(subkey_name, name)?;
+        let class_len = copy_name(&subkey.class, class)?;
+        let last_write_time = filetime_from_system_time(subkey.last_write_time.unwrap_or(std::time::UNIX_EPOCH));
+        Ok((name_len, class_len, last_write_time))
+    }
+
+    fn enum_value(&self, index: u32, name: &mut [u16], data: Option<&mut [u8]>, data_len: &mut usize) -> Result<(usize,u32)> {
+        let node = self.0.lock().unwrap();
+        let (value_name, value) = node.values.get(index as usize).ok_or(Error{code:ERROR_NO_MORE_ITEMS})?;
+        let bytes = value.encode();
+        *data_len = bytes.len();
+        let name_len = copy_name(value_name, name)?;
+        if let Some(data) = data {
+            if data.len() < bytes.len() {
+                return Err(Error{code:ERROR_MORE_DATA});
+            }
+            data[..bytes.len()].copy_from_slice(&bytes);
+        }
+        Ok((name_len, value.value_type()))
+    }
+}
+
+fn to_string(wide: Vec<u16>) -> String {
+    String::from_utf16_lossy(&wide)
+}
+
+pub struct MemoryValueIterator(Enumeration<MemoryKey>);
+pub struct MemoryValueNameIterator(Enumeration<MemoryKey>);
+pub struct MemoryKeyNameIterator(Enumeration<MemoryKey>);
+pub struct MemorySubkeyIterator(Enumeration<MemoryKey>);
+
+impl Iterator for MemoryValueIterator {
+    type Item=Result<(String,ValueBuf)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next(|buffers, key, index|buffers.value(key, index).map(|(name, value)|(to_string(name), value)))
+    }
+}
+
+impl Iterator for MemoryValueNameIterator {
+    type Item=Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next(|buffers, key, index|buffers.value_name(key, index).map(to_string))
+    }
+}
+
+impl Iterator for MemoryKeyNameIterator {
+    type Item=Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next(|buffers, key, index|buffers.subkey(key, index).map(|subkey|to_string(subkey.name)))
+    }
+}
+
+impl Iterator for MemorySubkeyIterator {
+    type Item=Result<Subkey<String>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next(|buffers, key, index|buffers.subkey(key, index).map(|subkey|Subkey{
+            name: to_string(subkey.name),
+            class: to_string(subkey.class),
+            last_write_time: subkey.last_write_time,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keys_and_values() {
+        let root = MemoryKey::new();
+        let key = root.create("Services\\hello\\Configuration").unwrap();
+        key.set_value("Port", &ValueBuf::Dword(80)).unwrap();
+        key.set_value("port", &ValueBuf::Dword(8080)).unwrap();
+        assert_eq!(root.open("services\\HELLO\\configuration").unwrap().query_value("PORT").unwrap(), ValueBuf::Dword(8080));
+        assert_eq!(root.open("Services\\missing").unwrap_err(), Error{code:ERROR_FILE_NOT_FOUND});
+
+        assert_eq!(root.delete_key("Services\\hello").unwrap_err(), Error{code:ERROR_ACCESS_DENIED});
+        root.delete_tree("Services\\hello").unwrap();
+        assert!(root.open("Services\\hello").is_err());
+        assert_eq!(root.info().subkeys, 1);
+    }
+
+    #[test]
+    fn staging() {
+        let root = MemoryKey::new();
+        root.create("Configuration").unwrap().set_value("port", &ValueBuf::Dword(80)).unwrap();
+
+        let staged = root.stage();
+        staged.key().open("Configuration").unwrap().set_value("port", &ValueBuf::Dword(8080)).unwrap();
+        staged.key().create("Configuration\\tls").unwrap();
+        assert_eq!(root.open("Configuration").unwrap().query_value("port").unwrap(), ValueBuf::Dword(80));
+        drop(staged);
+        assert_eq!(root.open("Configuration").unwrap().query_value("port").unwrap(), ValueBuf::Dword(80));
+        assert!(root.open("Configuration\\tls").is_err());
+
+        let staged = root.stage();
+        staged.key().open("Configuration").unwrap().set_value("port", &ValueBuf::Dword(8080)).unwrap();
+        staged.key().create("Configuration\\tls").unwrap();
+        staged.commit();
+        assert_eq!(root.open("Configuration").unwrap().query_value("port").unwrap(), ValueBuf::Dword(8080));
+        assert!(root.open("Configuration\\tls").is_ok());
+    }
+
+    #[cfg(feature = "serde_config")]
+    mod object {
+        use serde::{Serialize, Serializer, Deserialize, ser::Error as _};
+
+        #[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+        pub struct Config {
+            pub port: u32,
+            pub name: String,
+        }
+
+        /// fails once `port` has been written
+        #[derive(Serialize)]
+        pub struct Broken {
+            pub port: u32,
+            pub name: Failing,
+        }
+
+        pub struct Failing;
+
+        impl Serialize for Failing {
+            fn serialize<S: Serializer>(&self, _: S) -> std::result::Result<S::Ok, S::Error> {
+                Err(S::Error::custom("failing"))
+            }
+        }
+    }
+
+    #[cfg(feature = "serde_config")]
+    #[test]
+    fn staged_object() {
+        use object::*;
+
+        let root = MemoryKey::new();
+        root.set_object("Configuration", &Config{ port: 80, name: "old".into() }).unwrap();
+
+        let staged = root.stage();
+        assert_eq!(
+            staged.key().set_object("Configuration", &Broken{ port: 8080, name: Failing }),
+            Err(Error{code:crate::registry::ERROR_INVALID_DATA}),
+        );
+        assert_eq!(staged.key().open("Configuration").unwrap().query_value("port").unwrap(), ValueBuf::Dword(8080));
+        drop(staged);
+        assert_eq!(root.query_object::<Config>("Configuration").unwrap(), Config{ port: 80, name: "old".into() });
+
+        let staged = root.stage();
+        staged.key().set_object("Configuration", &Config{ port: 8080, name: "new".into() }).unwrap();
+        staged.commit();
+        assert_eq!(root.query_object::<Config>("Configuration").unwrap(), Config{ port: 8080, name: "new".into() });
+    }
+
+    #[test]
+    fn long_names() {
+        let root = MemoryKey::new();
+        let long = "v".repeat(16383);
+        root.set_value("short", &ValueBuf::from("x")).unwrap();
+        root.set_value(&long, &ValueBuf::Binary(vec![7; 100_000])).unwrap();
+        root.create(&"k".repeat(255)).unwrap().set_class(&"c".repeat(1000));
+
+        let values = root.iter_values().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(values, vec![
+            ("short".into(), ValueBuf::from("x")),
+            (long.clone(), ValueBuf::Binary(vec![7; 100_000])),
+        ]);
+        assert_eq!(root.iter_value_names().collect::<Result<Vec<_>>>().unwrap(), vec!["short".to_string(), long]);
+
+        let subkeys = root.iter_subkeys().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(subkeys.len(), 1);
+        assert_eq!(subkeys[0].name, "k".repeat(255));
+        assert_eq!(subkeys[0].class, "c".repeat(1000));
+        assert!(subkeys[0].last_write_time > std::time::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn names_too_long() {
+        let root = MemoryKey::new();
+        root.set_value(&"v".repeat(40000), &ValueBuf::Dword(1)).unwrap();
+        let mut values = root.iter_value_names();
+        assert_eq!(values.next(), Some(Err(Error{code:ERROR_MORE_DATA})));
+        assert_eq!(values.next(), None);
+    }
+}
@@ -1,9 +1,29 @@
 
+#[cfg(windows)]
 mod ext;
+#[cfg(windows)]
 mod key;
+#[cfg(windows)]
+mod watch;
 mod value;
-mod maybe_uninit_value;
+mod info;
+mod enumerate;
+mod memory;
+mod tree;
+mod reg_file;
+mod regf;
 
+// win32 error codes returned by the portable parts, spelled out so they
+// build on every platform
+pub(crate) const ERROR_FILE_NOT_FOUND: u32 = 2;
+pub(crate) const ERROR_ACCESS_DENIED: u32 = 5;
+pub(crate) const ERROR_INVALID_DATA: u32 = 13;
+pub(crate) const ERROR_BAD_PATHNAME: u32 = 161;
+pub(crate) const ERROR_MORE_DATA: u32 = 234;
+pub(crate) const ERROR_NO_MORE_ITEMS: u32 = 259;
+pub(crate) const ERROR_BADDB: u32 = 1009;
+
+#[cfg(windows)]
 use std::{
     marker::PhantomData,
     ffi::{
@@ -12,63 +32,58 @@ use std::{
     },
 };
 
+#[cfg(windows)]
 use winapi::{
     shared::{
         minwindef::HKEY,
-        winerror::{
-            ERROR_INVALID_DATA,
-        },
     },
 };
 
+#[cfg(windows)]
 use super::{Error,Result,to_wstr};
-use maybe_uninit_value::MaybeUninitValue;
 
+#[cfg(windows)]
 pub type Name = OsStr;
+#[cfg(windows)]
 pub type NameBuf = OsString;
 
-pub use key::Key;
-pub use ext::{KeyExt,ValueIterator,KeyNameIterator,ValueNameIterator};
-pub use value::ValueBuf;
+#[cfg(windows)]
+pub use key::{Key,KeyAccess,View,Disposition,OpenOptions,Transaction};
+#[cfg(windows)]
+pub use watch::{ChangeFilter,KeyChanges};
+#[cfg(all(windows, feature = "serde_config", feature = "async_main"))]
+pub use watch::ObjectWatch;
+#[cfg(windows)]
+pub use ext::{KeyExt,ValueIterator,KeyNameIterator,ValueNameIterator,SubkeyIterator};
+pub use value::*;
+pub use info::*;
+pub use enumerate::Subkey;
+pub use memory::*;
+pub use tree::KeyTree;
+pub use reg_file::*;
+pub use regf::*;
 
-pub use key::HKEY_CURRENT_USER;
-pub use key::HKEY_LOCAL_MACHINE;
+#[cfg(windows)]
+pub use key::{
+    HKEY_CLASSES_ROOT,
+    HKEY_CURRENT_USER,
+    HKEY_LOCAL_MACHINE,
+    HKEY_USERS,
+    HKEY_PERFORMANCE_DATA,
+    HKEY_CURRENT_CONFIG,
+};
 
+#[cfg(windows)]
 struct KeyRef<'a>(HKEY, PhantomData<&'a Key>);
 
+#[cfg(windows)]
 impl<'a> KeyExt for KeyRef<'a> {
     unsafe fn handle(&self) -> HKEY { self.0 }
 }
 
+#[cfg(windows)]
 impl<'a> From<&'a Key> for KeyRef<'a> {
     fn from(src: &'a Key) -> Self {
         Self(src.0, Default::default())
     }
 }
-
-// transmute [u8] slice to [u16] if size and alignment are correct
-unsafe fn _to_wide_slice(src: &[u8]) -> Result<&[u16]> {
-    let (head,body,tail) = src.align_to::<u16>();
-    if head.is_empty() && tail.is_empty() {
-        Ok(body)
-    } else {
-        Err(Error{code:ERROR_INVALID_DATA})
-    }
-}
-
-unsafe fn _to_narrow_slice(src: &[u16]) -> Result<&[u8]> {
-    let (head,body,tail) = src.align_to::<u8>();
-    if head.is_empty() && tail.is_empty() {
-        Ok(body)
-    } else {
-        Err(Error{code:ERROR_INVALID_DATA})
-    }
-}
-unsafe fn to_narrow_slice_mut(src: &mut[u16]) -> Result<&mut[u8]> {
-    let (head,body,tail) = src.align_to_mut::<u8>();
-    if head.is_empty() && tail.is_empty() {
-        Ok(body)
-    } else {
-        Err(Error{code:ERROR_INVALID_DATA})
-    }
-}
\ No newline at end of file
@@ -0,0 +1,378 @@
+//! `.reg` files
+//!
+//! The `Windows Registry Editor Version 5.00` format that regedit imports and
+//! exports. A file is a list of sections, `[path]` sets the values listed
+//! under it and `[-path]` deletes the key with everything below it. Values
+//! are `"name"="string"`, `dword:` followed by eight hex digits, `hex:` for
+//! binary data or `hex(type):` for any other type, as comma separated bytes
+//! that may continue over several lines ending in `\`. `-` deletes a value and
+//! `@` names the default value.
+//!
+//! regedit writes UTF-16 with a byte order mark and CRLF line endings, both
+//! are accepted along with UTF-8 and LF.
+
+use std::fmt::{self, Write};
+
+use super::{
+    KeyTree, ValueBuf, REG_BINARY,
+    ERROR_FILE_NOT_FOUND, ERROR_ACCESS_DENIED, ERROR_BAD_PATHNAME,
+};
+use crate::{Error, Result};
+
+pub const REG_FILE_HEADER: &str = "Windows Registry Editor Version 5.00";
+
+/// lines of hex data are wrapped before they get longer than this
+const LINE_LEN: usize = 80;
+
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub enum Section {
+    /// create the key if needed and set the values, `None` deletes one
+    Key{path: String, values: Vec<(String,Option<ValueBuf>)>},
+    /// delete the key and everything below it
+    DeleteKey(String),
+}
+
+/// the contents of a `.reg` file
+#[derive(Clone,Debug,Default,Eq,PartialEq)]
+pub struct RegFile {
+    pub sections: Vec<Section>,
+}
+
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub struct ParseError {
+    /// the line the error is on, counting from 1, or 0 for the whole file
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// join the lines continued with `\`, numbering each by its first line
+fn logical_lines(text: &str) -> Vec<(usize,String)> {
+    let mut lines = Vec::new();
+    let mut pending: Option<(usize,String)> = None;
+    for (index, line) in text.split('\n').enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let (number, mut line) = match pending.take() {
+            Some((number, start)) => (number, start + line.trim_start()),
+            None => (index + 1, line.to_string()),
+        };
+        let is_value = line.starts_with('"') || line.starts_with('@');
+        if is_value && line.trim_end().ends_with('\\') {
+            line.truncate(line.trim_end().len() - 1);
+            pending = Some((number, line));
+        } else {
+            lines.push((number, line));
+        }
+    }
+    lines.extend(pending);
+    lines
+}
+
+/// parse a quoted string with `\` escapes, returning it and the rest
+fn parse_quoted(text: &str) -> std::result::Result<(String,&str), &'static str> {
+    let mut chars = text.strip_prefix('"').ok_or("expected a quoted string")?.char_indices();
+    let mut string = String::new();
+    while let Some((_, c)) = chars.next() {
+        match c {
+            '\\' => string.push(chars.next().ok_or("unterminated string")?.1),
+            '"' => return Ok((string, chars.as_str())),
+            c => string.push(c),
+        }
+    }
+    Err("unterminated string")
+}
+
+fn parse_hex(text: &str) -> std::result::Result<Vec<u8>, &'static str> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|byte|!byte.is_empty())
+        .map(|byte|u8::from_str_radix(byte, 16).map_err(|_|"invalid hex byte"))
+        .collect()
+}
+
+fn parse_data(text: &str) -> std::result::Result<Option<ValueBuf>, &'static str> {
+    let text = text.trim();
+    if text == "-" {
+        Ok(None)
+    } else if text.starts_with('"') {
+        let (string, rest) = parse_quoted(text)?;
+        if !rest.trim().is_empty() {
+            return Err("unexpected text after the string");
+        }
+        Ok(Some(ValueBuf::from(string)))
+    } else if let Some(digits) = text.strip_prefix("dword:") {
+        u32::from_str_radix(digits, 16).map(|value|Some(ValueBuf::Dword(value))).map_err(|_|"invalid dword")
+    } else if let Some(rest) = text.strip_prefix("hex") {
+        let (value_type, bytes) = match rest.strip_prefix(':') {
+            Some(bytes) => (REG_BINARY, bytes),
+            None => {
+                let rest = rest.strip_prefix('(').ok_or("expected hex: or hex(type):")?;
+                let (value_type, bytes) = rest.split_once("):").ok_or("expected hex(type):")?;
+                (u32::from_str_radix(value_type, 16).map_err(|_|"invalid value type")?, bytes)
+            },
+        };
+        ValueBuf::decode(value_type, &parse_hex(bytes)?).map(Some).map_err(|_|"data does not fit the value type")
+    } else {
+        Err("unknown value format")
+    }
+}
+
+fn parse_value(line: &str) -> std::result::Result<(String,Option<ValueBuf>), &'static str> {
+    let (name, rest) = match line.strip_prefix('@') {
+        Some(rest) => (String::new(), rest),
+        None => parse_quoted(line)?,
+    };
+    let data = rest.trim_start().strip_prefix('=').ok_or("expected =")?;
+    Ok((name, parse_data(data)?))
+}
+
+fn quote(string: &str) -> String {
+    format!("\"{}\"", string.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// write `"name"=hex(type):` and the data, wrapping lines as regedit does
+fn write_hex(out: &mut String, name: &str, value: &ValueBuf) {
+    let mut line = match value.value_type() {
+        REG_BINARY => format!("{}=hex:", name),
+        value_type => format!("{}=hex({:x}):", name, value_type),
+    };
+    let bytes = value.encode();
+    let mut on_line = 0;
+    for (index, byte) in bytes.iter().enumerate() {
+        let item = if index + 1 == bytes.len() { format!("{:02x}", byte) } else { format!("{:02x},", byte) };
+        // leave room for the `\`
+        if on_line > 0 && line.len() + item.len() + 1 > LINE_LEN {
+            out.push_str(&line);
+            out.push_str("\\\r\n");
+            line = "  ".into();
+            on_line = 0;
+        }
+        line.push_str(&item);
+        on_line += 1;
+    }
+    out.push_str(&line);
+    out.push_str("\r\n");
+}
+
+fn write_value(out: &mut String, name: &str, value: &Option<ValueBuf>) {
+    let name = if name.is_empty() { "@".into() } else { quote(name) };
+    match value {
+        None => {
+            let _ = write!(out, "{}=-\r\n", name);
+        },
+        Some(ValueBuf::Dword(value)) => {
+            let _ = write!(out, "{}=dword:{:08x}\r\n", name, value);
+        },
+        Some(value @ ValueBuf::String(string)) => match String::from_utf16(string) {
+            // line breaks can not be quoted
+            Ok(string) if !string.contains(['\r', '\n']) => {
+                let _ = write!(out, "{}={}\r\n", name, quote(&string));
+            },
+            _ => write_hex(out, &name, value),
+        },
+        Some(value) => write_hex(out, &name, value),
+    }
+}
+
+fn ignore_not_found(result: Result<()>) -> Result<()> {
+    match result {
+        Err(Error{code: ERROR_FILE_NOT_FOUND}) => Ok(()),
+        result => result,
+    }
+}
+
+/// the part of `path` below `root`, both compared without regard to case
+fn relative<'a>(root: &str, path: &'a str) -> Result<&'a str> {
+    let root = root.trim_end_matches('\\');
+    let rest = match path.get(..root.len()) {
+        Some(start) if start.eq_ignore_ascii_case(root) => &path[root.len()..],
+        _ => return Err(Error{code:ERROR_BAD_PATHNAME}),
+    };
+    if rest.is_empty() {
+        Ok(rest)
+    } else {
+        rest.strip_prefix('\\').ok_or(Error{code:ERROR_BAD_PATHNAME})
+    }
+}
+
+fn export_key<K: KeyTree>(key: &K, path: &str, sections: &mut Vec<Section>) -> Result<()> {
+    let values = key.values()?.into_iter().map(|(name, value)|(name, Some(value))).collect();
+    sections.push(Section::Key{path: path.into(), values});
+    for name in key.subkey_names()? {
+        export_key(&key.open_subkey(&name)?, &format!("{}\\{}", path, name), sections)?;
+    }
+    Ok(())
+}
+
+impl RegFile {
+    pub fn parse(text: &str) -> std::result::Result<Self, ParseError> {
+        let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+        let mut lines = logical_lines(text).into_iter()
+            .filter(|(_, line)|!line.trim().is_empty() && !line.starts_with(';'));
+
+        match lines.next() {
+            Some((_, header)) if header.trim() == REG_FILE_HEADER => (),
+            Some((line, _)) => return Err(ParseError{ line, reason: "expected the Windows Registry Editor Version 5.00 header" }),
+            None => return Err(ParseError{ line: 0, reason: "empty file" }),
+        }
+
+        let mut file = RegFile::default();
+        for (line, text) in lines {
+            let text = text.trim_end();
+            if let Some(path) = text.strip_prefix('[') {
+                let path = path.strip_suffix(']').ok_or(ParseError{ line, reason: "expected ]" })?;
+                file.sections.push(match path.strip_prefix('-') {
+                    Some(path) => Section::DeleteKey(path.into()),
+                    None => Section::Key{path: path.into(), values: Vec::new()},
+                });
+            } else {
+                let value = parse_value(text).map_err(|reason|ParseError{ line, reason })?;
+                match file.sections.last_mut() {
+                    Some(Section::Key{values, ..}) => values.push(value),
+                    _ => return Err(ParseError{ line, reason: "value outside of a key" }),
+                }
+            }
+        }
+        Ok(file)
+    }
+
+    /// parse a file as regedit writes it, UTF-16 with a byte order mark, or
+    /// UTF-8
+    pub fn from_bytes(bytes: &[u8]) -> std::result::Result<Self, ParseError> {
+        const NOT_TEXT: ParseError = ParseError{ line: 0, reason: "not UTF-16 or UTF-8 text" };
+        match bytes.strip_prefix(&[0xff, 0xfe]) {
+            Some(bytes) if bytes.len() % 2 == 0 => {
+                let wide: Vec<u16> = bytes.chunks(2).map(|c|u16::from_le_bytes([c[0], c[1]])).collect();
+                Self::parse(&String::from_utf16(&wide).map_err(|_|NOT_TEXT)?)
+            },
+            Some(_) => Err(NOT_TEXT),
+            None => Self::parse(std::str::from_utf8(bytes).map_err(|_|NOT_TEXT)?),
+        }
+    }
+
+    /// the file as regedit writes it, UTF-16 with a byte order mark
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0xff, 0xfe];
+        for c in self.to_string().encode_utf16() {
+            bytes.extend_from_slice(&c.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// every value of `key` and its subkeys, with `path` naming `key`, e.g.
+    /// `HKEY_LOCAL_MACHINE\SYSTEM\CurrentControlSet\Services\hello`
+    pub fn export(key: &impl KeyTree, path: &str) -> Result<Self> {
+        let mut sections = Vec::new();
+        export_key(key, path, &mut sections)?;
+        Ok(Self{ sections })
+    }
+
+    /// make the changes to `key`, which `path` names, every section must be
+    /// `path` or below it
+    ///
+    /// Deleting what does not exist is not an error.
+    pub fn apply(&self, key: &impl KeyTree, path: &str) -> Result<()> {
+        for section in &self.sections {
+            match section {
+                Section::Key{path: key_path, values} => {
+                    let subkey = key.create_subkey(relative(path, key_path)?)?;
+                    for (name, value) in values {
+                        match value {
+                            Some(value) => subkey.put_value(name, value)?,
+                            None => ignore_not_found(subkey.remove_value(name))?,
+                        }
+                    }
+                },
+                Section::DeleteKey(key_path) => {
+                    match relative(path, key_path)? {
+                        "" => return Err(Error{code:ERROR_ACCESS_DENIED}),
+                        key_path => ignore_not_found(key.remove_subtree(key_path))?,
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for RegFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::new();
+        out.push_str(REG_FILE_HEADER);
+        out.push_str("\r\n\r\n");
+        for section in &self.sections {
+            match section {
+                Section::Key{path, values} => {
+                    let _ = write!(out, "[{}]\r\n", path);
+                    for (name, value) in values {
+                        write_value(&mut out, name, value);
+                    }
+                },
+                Section::DeleteKey(path) => {
+                    let _ = write!(out, "[-{}]\r\n", path);
+                },
+            }
+            out.push_str("\r\n");
+        }
+        f.write_str(&out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::registry::MemoryKey;
+
+    const ROOT: &str = "HKEY_LOCAL_MACHINE\\SYSTEM\\CurrentControlSet\\Services\\hello";
+
+    fn service() -> MemoryKey {
+        let root = MemoryKey::new();
+        RegFile::parse(include_str!("testdata/service.reg")).unwrap().apply(&root, ROOT).unwrap();
+        root
+    }
+
+    #[test]
+    fn golden_export() {
+        let root = service();
+        let config = root.open("Configuration").unwrap();
+        assert_eq!(config.query_value("").unwrap(), ValueBuf::from("default"));
+        assert_eq!(config.query_value("path").unwrap(), ValueBuf::from("C:\\Program Files\\hello \"quoted\""));
+        assert_eq!(config.query_value("port").unwrap(), ValueBuf::Dword(8080));
+        assert_eq!(config.query_value("limit").unwrap(), ValueBuf::Qword(0x1_0000_0000));
+        assert_eq!(config.query_value("servers").unwrap(), vec!["alpha", "beta"].into_iter().collect());
+        assert_eq!(config.query_value("certificate").unwrap(), ValueBuf::Binary((0..=99).collect()));
+
+        assert_eq!(RegFile::export(&root, ROOT).unwrap().to_string(), include_str!("testdata/service.reg"));
+    }
+
+    #[test]
+    fn golden_edit() {
+        let root = service();
+        RegFile::parse(include_str!("testdata/edit.reg")).unwrap().apply(&root, ROOT).unwrap();
+        assert_eq!(RegFile::export(&root, ROOT).unwrap().to_string(), include_str!("testdata/edited.reg"));
+    }
+
+    #[test]
+    fn utf16() {
+        let file = RegFile::parse(include_str!("testdata/service.reg")).unwrap();
+        assert_eq!(RegFile::from_bytes(&file.to_bytes()).unwrap(), file);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(RegFile::parse("REGEDIT4\r\n").unwrap_err(), ParseError{ line: 1, reason: "expected the Windows Registry Editor Version 5.00 header" });
+        assert_eq!(RegFile::parse(&format!("{}\n\n\"a\"=dword:1\n", REG_FILE_HEADER)).unwrap_err().line, 3);
+        assert_eq!(RegFile::parse(&format!("{}\n[a]\n\"a\"=hex:01,\\\n  0g\n", REG_FILE_HEADER)).unwrap_err(), ParseError{ line: 3, reason: "invalid hex byte" });
+        assert_eq!(RegFile::parse(&format!("{}\n[a]\n\"a\"=hex(b):01\n", REG_FILE_HEADER)).unwrap_err().reason, "data does not fit the value type");
+
+        let file = RegFile::parse(&format!("{}\n[HKEY_CURRENT_USER\\other]\n", REG_FILE_HEADER)).unwrap();
+        assert_eq!(file.apply(&MemoryKey::new(), ROOT).unwrap_err(), Error{code:ERROR_BAD_PATHNAME});
+    }
+}
@@ -0,0 +1,955 @@
+//! Offline registry hives
+//!
+//! A read-only parser for the regf format of hive files such as a copy of
+//! `C:\Windows\System32\config\SYSTEM`. A 4096 byte base block is followed by
+//! hive bins, which are divided into cells: `nk` cells for keys, `vk` cells
+//! for values, `lf`, `lh`, `li` and `ri` cells listing subkeys, and `db`
+//! cells splitting data too large for one cell. Cell offsets count from the
+//! first bin.
+//!
+//! Changes still in the transaction logs (`.LOG1`, `.LOG2`) of a dirty hive
+//! are not applied. A hive has no `CurrentControlSet`, `Select\Current` holds
+//! the number of the control set in use. Anything malformed fails with
+//! `ERROR_BADDB`, as do keys more than 512 levels deep. Writing fails with
+//! `ERROR_ACCESS_DENIED`.
+
+use std::{sync::Arc, time::SystemTime};
+
+use super::{
+    ValueBuf, KeyInfo, KeyTree, Subkey, system_time_from_filetime,
+    enumerate::{RawEnum, Buffers, Enumeration},
+    ERROR_FILE_NOT_FOUND, ERROR_ACCESS_DENIED, ERROR_MORE_DATA, ERROR_NO_MORE_ITEMS, ERROR_BADDB,
+};
+use crate::{Error, Result};
+
+const BASE_BLOCK_LEN: usize = 4096;
+/// no cell offset, e.g. of the subkey list of a key without subkeys
+const NO_CELL: u32 = 0xffff_ffff;
+/// the most data a `db` segment holds, larger data is stored as segments in
+/// hives of version 1.4 and later
+const BIG_DATA_SEGMENT_LEN: usize = 16344;
+
+/// `nk` flag, the name is Latin-1 rather than UTF-16
+const KEY_COMP_NAME: u16 = 0x0020;
+/// `vk` flag, the name is Latin-1 rather than UTF-16
+const VALUE_COMP_NAME: u16 = 0x0001;
+/// set in the `vk` data length when the data is in the offset field
+const DATA_INLINE: u32 = 0x8000_0000;
+/// the deepest a key may be below the root, as in the windows registry, a
+/// corrupt hive whose subkeys form a cycle fails here
+const MAX_DEPTH: u16 = 512;
+
+const BAD_HIVE: Error = Error{code:ERROR_BADDB};
+
+fn bytes_at(cell: &[u8], pos: usize, len: usize) -> Result<&[u8]> {
+    cell.get(pos..pos.checked_add(len).ok_or(BAD_HIVE)?).ok_or(BAD_HIVE)
+}
+
+fn u16_at(cell: &[u8], pos: usize) -> Result<u16> {
+    let mut bytes = [0; 2];
+    bytes.copy_from_slice(bytes_at(cell, pos, 2)?);
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn u32_at(cell: &[u8], pos: usize) -> Result<u32> {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(bytes_at(cell, pos, 4)?);
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn u64_at(cell: &[u8], pos: usize) -> Result<u64> {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(bytes_at(cell, pos, 8)?);
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// a name as UTF-16, widening a compressed name
+fn wide_name(bytes: &[u8], compressed: bool) -> Vec<u16> {
+    if compressed {
+        bytes.iter().map(|byte|*byte as u16).collect()
+    } else {
+        bytes.chunks_exact(2).map(|pair|u16::from_le_bytes([pair[0], pair[1]])).collect()
+    }
+}
+
+/// copy `name` into `buffer` with a NUL, as the win32 functions do
+fn copy_name(name: &[u16], buffer: &mut [u16]) -> Result<usize> {
+    if name.len() < buffer.len() {
+        buffer[..name.len()].copy_from_slice(name);
+        buffer[name.len()] = 0;
+        Ok(name.len())
+    } else {
+        Err(Error{code:ERROR_MORE_DATA})
+    }
+}
+
+/// upper case a name one UTF-16 unit at a time, as the registry compares
+/// names, keeping characters whose upper case is longer, e.g. `ß`
+fn fold(name: &str) -> String {
+    name.chars().map(|c| {
+        let mut upper = c.to_uppercase();
+        match (upper.next(), upper.next()) {
+            (Some(upper), None) if c.len_utf16() == 1 && upper.len_utf16() == 1 => upper,
+            _ => c,
+        }
+    }).collect()
+}
+
+struct HiveData {
+    bytes: Vec<u8>,
+    root: u32,
+    minor_version: u32,
+    dirty: bool,
+}
+
+/// the contents of a hive file, clones share them
+#[derive(Clone)]
+pub struct Hive(Arc<HiveData>);
+
+impl Hive {
+    /// check the base block of the contents of a hive file, the cells are
+    /// only checked as they are read
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        if bytes.len() < BASE_BLOCK_LEN || &bytes[..4] != b"regf" || bytes_at(&bytes, BASE_BLOCK_LEN, 4)? != b"hbin" {
+            return Err(BAD_HIVE);
+        }
+        let major_version = u32_at(&bytes, 0x14)?;
+        let minor_version = u32_at(&bytes, 0x18)?;
+        if major_version != 1 {
+            return Err(BAD_HIVE);
+        }
+        let root = u32_at(&bytes, 0x24)?;
+        let dirty = u32_at(&bytes, 0x04)? != u32_at(&bytes, 0x08)?;
+        Ok(Self(Arc::new(HiveData{ bytes, root, minor_version, dirty })))
+    }
+
+    /// whether the hive was not completely written, its logs hold changes
+    /// that are missing here
+    pub fn is_dirty(&self) -> bool {
+        self.0.dirty
+    }
+
+    /// the root key, whose name is whatever the hive was created with
+    pub fn root(&self) -> Result<HiveKey> {
+        HiveKey::new(self.clone(), self.0.root, 0)
+    }
+
+    /// the data of the allocated cell at `offset`
+    fn cell(&self, offset: u32) -> Result<&[u8]> {
+        let start = BASE_BLOCK_LEN.checked_add(offset as usize).ok_or(BAD_HIVE)?;
+        let size = u32_at(&self.0.bytes, start)? as i32;
+        // allocated cells have a negative size, which includes the size field
+        if size >= 0 {
+            return Err(BAD_HIVE);
+        }
+        let len = (size.unsigned_abs() as usize).checked_sub(4).ok_or(BAD_HIVE)?;
+        bytes_at(&self.0.bytes, start + 4, len)
+    }
+
+    /// the data of a cell that starts with `signature`
+    fn signed_cell(&self, offset: u32, signature: &[u8; 2]) -> Result<&[u8]> {
+        let cell = self.cell(offset)?;
+        if bytes_at(cell, 0, 2)? == signature {
+            Ok(cell)
+        } else {
+            Err(BAD_HIVE)
+        }
+    }
+
+    /// the number of keys in the subkey list at `offset`
+    fn subkey_count(&self, offset: u32) -> Result<usize> {
+        let cell = self.cell(offset)?;
+        let count = u16_at(cell, 2)? as usize;
+        match bytes_at(cell, 0, 2)? {
+            b"lf" | b"lh" | b"li" => Ok(count),
+            b"ri" => (0..count).try_fold(0, |total, index|{
+                let list = self.cell(u32_at(cell, 4 + index * 4)?)?;
+                match bytes_at(list, 0, 2)? {
+                    b"lf" | b"lh" | b"li" => Ok(total + u16_at(list, 2)? as usize),
+                    _ => Err(BAD_HIVE),
+                }
+            }),
+            _ => Err(BAD_HIVE),
+        }
+    }
+
+    /// the offset of the key at `index` in the subkey list at `offset`
+    fn subkey_at(&self, offset: u32, mut index: usize) -> Result<Option<u32>> {
+        let cell = self.cell(offset)?;
+        let count = u16_at(cell, 2)? as usize;
+        match bytes_at(cell, 0, 2)? {
+            // an offset and a hint of the name or a hash of it
+            b"lf" | b"lh" if index < count => Ok(Some(u32_at(cell, 4 + index * 8)?)),
+            b"li" if index < count => Ok(Some(u32_at(cell, 4 + index * 4)?)),
+            b"lf" | b"lh" | b"li" => Ok(None),
+            // a list of lists, which do not nest further
+            b"ri" => {
+                for list in 0..count {
+                    let list = u32_at(cell, 4 + list * 4)?;
+                    let len = match bytes_at(self.cell(list)?, 0, 2)? {
+                        b"ri" => return Err(BAD_HIVE),
+                        _ => self.subkey_count(list)?,
+                    };
+                    if index < len {
+                        return self.subkey_at(list, index);
+                    }
+                    index -= len;
+                }
+                Ok(None)
+            },
+            _ => Err(BAD_HIVE),
+        }
+    }
+
+    /// the data of the `vk` cell `vk`
+    fn value_data(&self, vk: &[u8]) -> Result<Vec<u8>> {
+        let len = u32_at(vk, 0x04)?;
+        let offset = u32_at(vk, 0x08)?;
+        if len & DATA_INLINE != 0 {
+            let len = (len & !DATA_INLINE) as usize;
+            return offset.to_le_bytes().get(..len).map(<[u8]>::to_vec).ok_or(BAD_HIVE);
+        }
+        let len = len as usize;
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        if len > self.0.bytes.len() {
+            return Err(BAD_HIVE);
+        }
+        if len > BIG_DATA_SEGMENT_LEN && self.0.minor_version >= 4 {
+            let db = self.signed_cell(offset, b"db")?;
+            let segments = self.cell(u32_at(db, 0x04)?)?;
+            let mut data = Vec::with_capacity(len);
+            for index in 0..u16_at(db, 0x02)? as usize {
+                let segment = self.cell(u32_at(segments, index * 4)?)?;
+                let wanted = (len - data.len()).min(BIG_DATA_SEGMENT_LEN);
+                data.extend_from_slice(bytes_at(segment, 0, wanted)?);
+            }
+            if data.len() == len { Ok(data) } else { Err(BAD_HIVE) }
+        } else {
+            bytes_at(self.cell(offset)?, 0, len).map(<[u8]>::to_vec)
+        }
+    }
+}
+
+impl std::fmt::Debug for Hive {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Hive")
+            .field("len", &self.0.bytes.len())
+            .field("minor_version", &self.0.minor_version)
+            .field("dirty", &self.0.dirty)
+            .finish()
+    }
+}
+
+/// a key of a `Hive`, which it keeps alive
+#[derive(Clone)]
+pub struct HiveKey {
+    hive: Hive,
+    offset: u32,
+    /// levels below the root
+    depth: u16,
+}
+
+impl std::fmt::Debug for HiveKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("HiveKey")
+            .field("name", &self.name().unwrap_or_default())
+            .field("offset", &self.offset)
+            .finish()
+    }
+}
+
+// layout of an `nk` cell
+const NK_FLAGS: usize = 0x02;
+const NK_LAST_WRITE_TIME: usize = 0x04;
+const NK_SUBKEY_COUNT: usize = 0x14;
+const NK_SUBKEY_LIST: usize = 0x1c;
+const NK_VALUE_COUNT: usize = 0x24;
+const NK_VALUE_LIST: usize = 0x28;
+const NK_SECURITY: usize = 0x2c;
+const NK_CLASS: usize = 0x30;
+const NK_NAME_LEN: usize = 0x48;
+const NK_CLASS_LEN: usize = 0x4a;
+const NK_NAME: usize = 0x4c;
+
+// layout of a `vk` cell
+const VK_NAME_LEN: usize = 0x02;
+const VK_TYPE: usize = 0x0c;
+const VK_FLAGS: usize = 0x10;
+const VK_NAME: usize = 0x14;
+
+impl HiveKey {
+    fn new(hive: Hive, offset: u32, depth: u16) -> Result<Self> {
+        if depth > MAX_DEPTH {
+            return Err(BAD_HIVE);
+        }
+        hive.signed_cell(offset, b"nk")?;
+        Ok(Self{ hive, offset, depth })
+    }
+
+    fn nk(&self) -> Result<&[u8]> {
+        self.hive.signed_cell(self.offset, b"nk")
+    }
+
+    fn wide_name(&self) -> Result<Vec<u16>> {
+        let nk = self.nk()?;
+        let len = u16_at(nk, NK_NAME_LEN)? as usize;
+        Ok(wide_name(bytes_at(nk, NK_NAME, len)?, u16_at(nk, NK_FLAGS)? & KEY_COMP_NAME != 0))
+    }
+
+    fn wide_class(&self) -> Result<Vec<u16>> {
+        let nk = self.nk()?;
+        match u32_at(nk, NK_CLASS)? {
+            NO_CELL => Ok(Vec::new()),
+            class => {
+                let len = u16_at(nk, NK_CLASS_LEN)? as usize;
+                Ok(wide_name(bytes_at(self.hive.cell(class)?, 0, len)?, false))
+            },
+        }
+    }
+
+    fn subkey_count(&self) -> Result<usize> {
+        let nk = self.nk()?;
+        match u32_at(nk, NK_SUBKEY_LIST)? {
+            NO_CELL => Ok(0),
+            _ if u32_at(nk, NK_SUBKEY_COUNT)? == 0 => Ok(0),
+            list => self.hive.subkey_count(list),
+        }
+    }
+
+    fn subkey(&self, index: usize) -> Result<Option<HiveKey>> {
+        let nk = self.nk()?;
+        match u32_at(nk, NK_SUBKEY_LIST)? {
+            NO_CELL => Ok(None),
+            _ if u32_at(nk, NK_SUBKEY_COUNT)? == 0 => Ok(None),
+            list => self.hive.subkey_at(list, index)?.map(|offset|HiveKey::new(self.hive.clone(), offset, self.depth + 1)).transpose(),
+        }
+    }
+
+    fn value_count(&self) -> Result<usize> {
+        Ok(u32_at(self.nk()?, NK_VALUE_COUNT)? as usize)
+    }
+
+    /// the `vk` cell of the value at `index`
+    fn vk(&self, index: usize) -> Result<Option<&[u8]>> {
+        let nk = self.nk()?;
+        if index >= u32_at(nk, NK_VALUE_COUNT)? as usize {
+            return Ok(None);
+        }
+        let list = self.hive.cell(u32_at(nk, NK_VALUE_LIST)?)?;
+        self.hive.signed_cell(u32_at(list, index * 4)?, b"vk").map(Some)
+    }
+
+    fn value_name(vk: &[u8]) -> Result<Vec<u16>> {
+        let len = u16_at(vk, VK_NAME_LEN)? as usize;
+        Ok(wide_name(bytes_at(vk, VK_NAME, len)?, u16_at(vk, VK_FLAGS)? & VALUE_COMP_NAME != 0))
+    }
+
+    pub fn name(&self) -> Result<String> {
+        self.wide_name().map(|name|String::from_utf16_lossy(&name))
+    }
+
+    /// usually empty
+    pub fn class(&self) -> Result<String> {
+        self.wide_class().map(|class|String::from_utf16_lossy(&class))
+    }
+
+    pub fn last_write_time(&self) -> Result<SystemTime> {
+        Ok(system_time_from_filetime(u64_at(self.nk()?, NK_LAST_WRITE_TIME)?))
+    }
+
+    /// open a subkey, names are compared without regard to case
+    pub fn open(&self, path: &str) -> Result<HiveKey> {
+        let mut key = self.clone();
+        for name in path.split('\\').filter(|name|!name.is_empty()) {
+            let name = fold(name);
+            let mut index = 0;
+            key = loop {
+                match key.subkey(index)? {
+                    Some(subkey) if fold(&subkey.name()?) == name => break subkey,
+                    Some(_) => index += 1,
+                    None => return Err(Error{code:ERROR_FILE_NOT_FOUND}),
+                }
+            };
+        }
+        Ok(key)
+    }
+
+    /// the value called `name`, `""` is the default value
+    pub fn query_value(&self, name: &str) -> Result<ValueBuf> {
+        let name = fold(name);
+        for index in 0..self.value_count()? {
+            let vk = self.vk(index)?.ok_or(BAD_HIVE)?;
+            if fold(&String::from_utf16_lossy(&Self::value_name(vk)?)) == name {
+                return ValueBuf::decode(u32_at(vk, VK_TYPE)?, &self.hive.value_data(vk)?);
+            }
+        }
+        Err(Error{code:ERROR_FILE_NOT_FOUND})
+    }
+
+    pub fn info(&self) -> Result<KeyInfo> {
+        let nk = self.nk()?;
+        let mut info = KeyInfo{
+            subkeys: 0,
+            max_subkey_name_len: 0,
+            max_class_len: 0,
+            values: 0,
+            max_value_name_len: 0,
+            max_value_len: 0,
+            security_descriptor_len: match u32_at(nk, NK_SECURITY)? {
+                NO_CELL => 0,
+                sk => u32_at(self.hive.signed_cell(sk, b"sk")?, 0x10)?,
+            },
+            last_write_time: self.last_write_time()?,
+        };
+        for index in 0..self.subkey_count()? {
+            let subkey = self.subkey(index)?.ok_or(BAD_HIVE)?;
+            info.subkeys += 1;
+            info.max_subkey_name_len = info.max_subkey_name_len.max(subkey.wide_name()?.len() as u32);
+            info.max_class_len = info.max_class_len.max(subkey.wide_class()?.len() as u32);
+        }
+        for index in 0..self.value_count()? {
+            let vk = self.vk(index)?.ok_or(BAD_HIVE)?;
+            info.values += 1;
+            info.max_value_name_len = info.max_value_name_len.max(Self::value_name(vk)?.len() as u32);
+            info.max_value_len = info.max_value_len.max(self.hive.value_data(vk)?.len() as u32);
+        }
+        Ok(info)
+    }
+
+    pub fn iter_values(&self) -> HiveValueIterator {
+        HiveValueIterator(Enumeration::new(self.clone(), Buffers::default()))
+    }
+
+    pub fn iter_value_names(&self) -> HiveValueNameIterator {
+        HiveValueNameIterator(Enumeration::new(self.clone(), Buffers::default()))
+    }
+
+    pub fn iter_key_names(&self) -> HiveKeyNameIterator {
+        HiveKeyNameIterator(Enumeration::new(self.clone(), Buffers::default()))
+    }
+
+    pub fn iter_subkeys(&self) -> HiveSubkeyIterator {
+        HiveSubkeyIterator(Enumeration::new(self.clone(), Buffers::default()))
+    }
+}
+
+impl RawEnum for HiveKey {
+    fn enum_key(&self, index: u32, name: &mut [u16], class: &mut [u16]) -> Result<(usize,usize,u64)> {
+        let subkey = self.subkey(index as usize)?.ok_or(Error{code:ERROR_NO_MORE_ITEMS})?;
+        let name_len = copy_name(&subkey.wide_name()?, name)?;
+        let class_len = copy_name(&subkey.wide_class()?, class)?;
+        Ok((name_len, class_len, u64_at(subkey.nk()?, NK_LAST_WRITE_TIME)?))
+    }
+
+    fn enum_value(&self, index: u32, name: &mut [u16], data: Option<&mut [u8]>, data_len: &mut usize) -> Result<(usize,u32)> {
+        let vk = self.vk(index as usize)?.ok_or(Error{code:ERROR_NO_MORE_ITEMS})?;
+        let bytes = self.hive.value_data(vk)?;
+        *data_len = bytes.len();
+        let name_len = copy_name(&Self::value_name(vk)?, name)?;
+        if let Some(data) = data {
+            if data.len() < bytes.len() {
+                return Err(Error{code:ERROR_MORE_DATA});
+            }
+            data[..bytes.len()].copy_from_slice(&bytes);
+        }
+        Ok((name_len, u32_at(vk, VK_TYPE)?))
+    }
+}
+
+fn to_string(wide: Vec<u16>) -> String {
+    String::from_utf16_lossy(&wide)
+}
+
+pub struct HiveValueIterator(Enumeration<HiveKey>);
+pub struct HiveValueNameIterator(Enumeration<HiveKey>);
+pub struct HiveKeyNameIterator(Enumeration<HiveKey>);
+pub struct HiveSubkeyIterator(Enumeration<HiveKey>);
+
+impl Iterator for HiveValueIterator {
+    type Item=Result<(String,ValueBuf)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next(|buffers, key, index|buffers.value(key, index).map(|(name, value)|(to_string(name), value)))
+    }
+}
+
+impl Iterator for HiveValueNameIterator {
+    type Item=Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next(|buffers, key, index|buffers.value_name(key, index).map(to_string))
+    }
+}
+
+impl Iterator for HiveKeyNameIterator {
+    type Item=Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next(|buffers, key, index|buffers.subkey(key, index).map(|subkey|to_string(subkey.name)))
+    }
+}
+
+impl Iterator for HiveSubkeyIterator {
+    type Item=Result<Subkey<String>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next(|buffers, key, index|buffers.subkey(key, index).map(|subkey|Subkey{
+            name: to_string(subkey.name),
+            class: to_string(subkey.class),
+            last_write_time: subkey.last_write_time,
+        }))
+    }
+}
+
+/// a hive is read-only, every change fails with `ERROR_ACCESS_DENIED`
+impl KeyTree for HiveKey {
+    fn open_subkey(&self, path: &str) -> Result<Self> { self.open(path) }
+    fn create_subkey(&self, _path: &str) -> Result<Self> { Err(Error{code:ERROR_ACCESS_DENIED}) }
+    fn subkey_names(&self) -> Result<Vec<String>> { self.iter_key_names().collect() }
+    fn values(&self) -> Result<Vec<(String,ValueBuf)>> { self.iter_values().collect() }
+    fn value_names(&self) -> Result<Vec<String>> { self.iter_value_names().collect() }
+    fn get_value(&self, name: &str) -> Result<ValueBuf> { self.query_value(name) }
+    fn put_value(&self, _name: &str, _value: &ValueBuf) -> Result<()> { Err(Error{code:ERROR_ACCESS_DENIED}) }
+    fn remove_value(&self, _name: &str) -> Result<()> { Err(Error{code:ERROR_ACCESS_DENIED}) }
+    fn remove_subtree(&self, _path: &str) -> Result<()> { Err(Error{code:ERROR_ACCESS_DENIED}) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::registry::{MemoryKey, RegFile};
+    use quickcheck::quickcheck;
+
+    // the layout written out again from the format description rather than
+    // taken from the parser, these shadow its constants so the fixtures do
+    // not share a wrong offset with it
+    const BASE_BLOCK_LEN: usize = 0x1000;
+    const HBIN_HEADER_LEN: usize = 0x20;
+    const NO_CELL: u32 = 0xffff_ffff;
+    const BIG_DATA_SEGMENT_LEN: usize = 16344;
+    const KEY_COMP_NAME: u16 = 0x0020;
+    const VALUE_COMP_NAME: u16 = 0x0001;
+    const DATA_INLINE: u32 = 0x8000_0000;
+    const NK_FLAGS: usize = 0x02;
+    const NK_LAST_WRITE_TIME: usize = 0x04;
+    const NK_SUBKEY_COUNT: usize = 0x14;
+    const NK_SUBKEY_LIST: usize = 0x1c;
+    const NK_VALUE_COUNT: usize = 0x24;
+    const NK_VALUE_LIST: usize = 0x28;
+    const NK_SECURITY: usize = 0x2c;
+    const NK_CLASS: usize = 0x30;
+    const NK_NAME_LEN: usize = 0x48;
+    const NK_CLASS_LEN: usize = 0x4a;
+    const NK_NAME: usize = 0x4c;
+    const VK_NAME_LEN: usize = 0x02;
+    const VK_TYPE: usize = 0x0c;
+    const VK_FLAGS: usize = 0x10;
+    const VK_NAME: usize = 0x14;
+
+    /// 2021-01-01T00:00:00Z
+    const LAST_WRITE_TIME: u64 = 132_539_328_000_000_000;
+
+    /// how a built key lists its subkeys
+    #[derive(Clone,Copy)]
+    enum List { Lf, Lh, Li, Ri }
+
+    /// writes the fixture hives, every cell goes in one bin
+    struct Builder {
+        minor_version: u32,
+        cells: Vec<u8>,
+    }
+
+    fn put(bytes: &mut [u8], pos: usize, data: &[u8]) {
+        bytes[pos..pos + data.len()].copy_from_slice(data);
+    }
+
+    fn offsets(offsets: &[u32]) -> Vec<u8> {
+        offsets.iter().flat_map(|offset|offset.to_le_bytes()).collect()
+    }
+
+    /// compressed when every character is Latin-1, as windows writes names
+    fn encode_name(name: &str) -> (Vec<u8>, bool) {
+        if name.chars().all(|c|(c as u32) < 0x100) {
+            (name.chars().map(|c|c as u8).collect(), true)
+        } else {
+            (name.encode_utf16().flat_map(u16::to_le_bytes).collect(), false)
+        }
+    }
+
+    impl Builder {
+        fn new(minor_version: u32) -> Self {
+            Self{ minor_version, cells: Vec::new() }
+        }
+
+        fn cell(&mut self, data: &[u8]) -> u32 {
+            let offset = HBIN_HEADER_LEN + self.cells.len();
+            let size = (data.len() + 4).div_ceil(8) * 8;
+            self.cells.extend_from_slice(&(-(size as i32)).to_le_bytes());
+            self.cells.extend_from_slice(data);
+            self.cells.resize(offset - HBIN_HEADER_LEN + size, 0);
+            offset as u32
+        }
+
+        fn value(&mut self, name: &str, value: &ValueBuf) -> u32 {
+            let data = value.encode();
+            let (len, offset) = if data.len() <= 4 {
+                let mut inline = [0; 4];
+                inline[..data.len()].copy_from_slice(&data);
+                (data.len() as u32 | DATA_INLINE, u32::from_le_bytes(inline))
+            } else if data.len() > BIG_DATA_SEGMENT_LEN && self.minor_version >= 4 {
+                let segments: Vec<u32> = data.chunks(BIG_DATA_SEGMENT_LEN).map(|segment|self.cell(segment)).collect();
+                let list = self.cell(&offsets(&segments));
+                let mut db = b"db".to_vec();
+                db.extend_from_slice(&(segments.len() as u16).to_le_bytes());
+                db.extend_from_slice(&list.to_le_bytes());
+                (data.len() as u32, self.cell(&db))
+            } else {
+                (data.len() as u32, self.cell(&data))
+            };
+            let (name, compressed) = encode_name(name);
+            let mut vk = vec![0; VK_NAME];
+            put(&mut vk, 0, b"vk");
+            put(&mut vk, VK_NAME_LEN, &(name.len() as u16).to_le_bytes());
+            put(&mut vk, 0x04, &len.to_le_bytes());
+            put(&mut vk, 0x08, &offset.to_le_bytes());
+            put(&mut vk, VK_TYPE, &value.value_type().to_le_bytes());
+            put(&mut vk, VK_FLAGS, &(if compressed { VALUE_COMP_NAME } else { 0 }).to_le_bytes());
+            vk.extend_from_slice(&name);
+            self.cell(&vk)
+        }
+
+        fn list(&mut self, list: List, subkeys: &[u32]) -> u32 {
+            let (signature, entries) = match list {
+                List::Lf => (b"lf", subkeys.iter().flat_map(|offset|[*offset, 0]).collect()),
+                List::Lh => (b"lh", subkeys.iter().flat_map(|offset|[*offset, 0]).collect()),
+                List::Li => (b"li", subkeys.to_vec()),
+                List::Ri => {
+                    let (first, second) = subkeys.split_at(subkeys.len() / 2);
+                    let lists = vec![self.list(List::Lf, first), self.list(List::Lh, second)];
+                    (b"ri", lists)
+                },
+            };
+            let mut cell = signature.to_vec();
+            cell.extend_from_slice(&(match list { List::Lf | List::Lh => entries.len() / 2, _ => entries.len() } as u16).to_le_bytes());
+            cell.extend_from_slice(&offsets(&entries));
+            self.cell(&cell)
+        }
+
+        /// subkeys are given sorted, as windows keeps them
+        fn key(&mut self, name: &str, class: &str, values: &[(&str, ValueBuf)], list: List, subkeys: &[u32]) -> u32 {
+            let values: Vec<u32> = values.iter().map(|(name, value)|self.value(name, value)).collect();
+            let value_list = if values.is_empty() { NO_CELL } else { self.cell(&offsets(&values)) };
+            let subkey_list = if subkeys.is_empty() { NO_CELL } else { self.list(list, subkeys) };
+            let class_bytes: Vec<u8> = class.encode_utf16().flat_map(u16::to_le_bytes).collect();
+            let class_cell = if class.is_empty() { NO_CELL } else { self.cell(&class_bytes) };
+            let (name, compressed) = encode_name(name);
+            let mut nk = vec![0; NK_NAME];
+            put(&mut nk, 0, b"nk");
+            put(&mut nk, NK_FLAGS, &(if compressed { KEY_COMP_NAME } else { 0 }).to_le_bytes());
+            put(&mut nk, NK_LAST_WRITE_TIME, &LAST_WRITE_TIME.to_le_bytes());
+            put(&mut nk, NK_SUBKEY_COUNT, &(subkeys.len() as u32).to_le_bytes());
+            put(&mut nk, NK_SUBKEY_LIST, &subkey_list.to_le_bytes());
+            put(&mut nk, 0x20, &NO_CELL.to_le_bytes());
+            put(&mut nk, NK_VALUE_COUNT, &(values.len() as u32).to_le_bytes());
+            put(&mut nk, NK_VALUE_LIST, &value_list.to_le_bytes());
+            put(&mut nk, NK_SECURITY, &NO_CELL.to_le_bytes());
+            put(&mut nk, NK_CLASS, &class_cell.to_le_bytes());
+            put(&mut nk, NK_NAME_LEN, &(name.len() as u16).to_le_bytes());
+            put(&mut nk, NK_CLASS_LEN, &(class_bytes.len() as u16).to_le_bytes());
+            nk.extend_from_slice(&name);
+            self.cell(&nk)
+        }
+
+        fn finish(self, root: u32) -> Vec<u8> {
+            let bin_len = (HBIN_HEADER_LEN + self.cells.len()).div_ceil(4096) * 4096;
+            let mut bytes = vec![0; BASE_BLOCK_LEN];
+            put(&mut bytes, 0, b"regf");
+            put(&mut bytes, 0x04, &1u32.to_le_bytes());
+            put(&mut bytes, 0x08, &1u32.to_le_bytes());
+            put(&mut bytes, 0x0c, &LAST_WRITE_TIME.to_le_bytes());
+            put(&mut bytes, 0x14, &1u32.to_le_bytes());
+            put(&mut bytes, 0x18, &self.minor_version.to_le_bytes());
+            put(&mut bytes, 0x20, &1u32.to_le_bytes());
+            put(&mut bytes, 0x24, &root.to_le_bytes());
+            put(&mut bytes, 0x28, &(bin_len as u32).to_le_bytes());
+            put(&mut bytes, 0x2c, &1u32.to_le_bytes());
+            let checksum = (0..0x1fc).step_by(4).fold(0, |checksum, pos|checksum ^ u32_at(&bytes, pos).unwrap());
+            put(&mut bytes, 0x1fc, &checksum.to_le_bytes());
+
+            let mut bin = vec![0; HBIN_HEADER_LEN];
+            put(&mut bin, 0, b"hbin");
+            put(&mut bin, 0x08, &(bin_len as u32).to_le_bytes());
+            bin.extend_from_slice(&self.cells);
+            // the rest of the bin is one free cell
+            let free = bin_len - bin.len();
+            bin.extend_from_slice(&(free as i32).to_le_bytes());
+            bin.resize(bin_len, 0);
+            bytes.extend_from_slice(&bin);
+            bytes
+        }
+    }
+
+    fn big_value() -> ValueBuf {
+        ValueBuf::Binary((0..40000u32).map(|i|(i % 251) as u8).collect())
+    }
+
+    fn parameters() -> Vec<(&'static str, ValueBuf)> {
+        vec![
+            ("", ValueBuf::from("default")),
+            ("Port", ValueBuf::Dword(8080)),
+            ("Empty", ValueBuf::Binary(Vec::new())),
+            ("Greeting", ValueBuf::from("Grüße ✓")),
+            ("Ünïcødé ✓", ValueBuf::Qword(1 << 40)),
+            ("Big", big_value()),
+        ]
+    }
+
+    /// a `SYSTEM` hive with the keys of a service called `hello`, using each
+    /// kind of subkey list
+    fn system_hive(minor_version: u32) -> Vec<u8> {
+        let mut builder = Builder::new(minor_version);
+        let limits = builder.key("limits", "", &[("max_connections", ValueBuf::Dword(16))], List::Lf, &[]);
+        let configuration = builder.key("Configuration", "", &[
+            ("listen_port", ValueBuf::Dword(8080)),
+            ("name", ValueBuf::from("hello")),
+        ], List::Li, &[limits]);
+        let parameters = builder.key("Parameters", "", &parameters(), List::Lf, &[]);
+        let hello = builder.key("hello", "service class", &[("Start", ValueBuf::Dword(2))], List::Li, &[configuration, parameters]);
+        let services: Vec<u32> = ["alpha", "beta", "hello", "zeta", "Ωmega"].iter()
+            .map(|name|if *name == "hello" { hello } else { builder.key(name, "", &[], List::Lf, &[]) })
+            .collect();
+        let services = builder.key("Services", "", &[], List::Ri, &services);
+        let control_set = builder.key("ControlSet001", "", &[], List::Lh, &[services]);
+        let select = builder.key("Select", "", &[("Current", ValueBuf::Dword(1))], List::Lf, &[]);
+        let root = builder.key("ROOT", "", &[], List::Lh, &[control_set, select]);
+        builder.finish(root)
+    }
+
+    fn hello(hive: &Hive) -> HiveKey {
+        hive.root().unwrap().open("ControlSet001\\Services\\hello").unwrap()
+    }
+
+    #[test]
+    fn navigation() {
+        let hive = Hive::from_bytes(system_hive(5)).unwrap();
+        assert!(!hive.is_dirty());
+        let root = hive.root().unwrap();
+        assert_eq!(root.name().unwrap(), "ROOT");
+        assert_eq!(root.subkey_names().unwrap(), ["ControlSet001", "Select"]);
+
+        let services = root.open("controlset001\\SERVICES").unwrap();
+        assert_eq!(services.subkey_names().unwrap(), ["alpha", "beta", "hello", "zeta", "Ωmega"]);
+        assert_eq!(services.open("ωMEGA").unwrap().name().unwrap(), "Ωmega");
+
+        let hello = services.open("Hello").unwrap();
+        assert_eq!(hello.class().unwrap(), "service class");
+        assert_eq!(hello.last_write_time().unwrap(), system_time_from_filetime(LAST_WRITE_TIME));
+        let subkeys: Vec<_> = services.iter_subkeys().collect::<Result<_>>().unwrap();
+        assert_eq!(subkeys[2], Subkey{
+            name: "hello".to_string(),
+            class: "service class".to_string(),
+            last_write_time: system_time_from_filetime(LAST_WRITE_TIME),
+        });
+
+        assert_eq!(root.open("ControlSet001\\Services\\missing").unwrap_err(), Error{code:ERROR_FILE_NOT_FOUND});
+        assert_eq!(root.open("Select").unwrap().query_value("current").unwrap(), ValueBuf::Dword(1));
+    }
+
+    #[test]
+    fn values() {
+        // version 1.3 keeps big data in one cell, later versions in `db` segments
+        for minor_version in [3, 5] {
+            let hive = Hive::from_bytes(system_hive(minor_version)).unwrap();
+            let key = hello(&hive).open("Parameters").unwrap();
+            let expected: Vec<(String, ValueBuf)> = parameters().into_iter().map(|(name, value)|(name.to_string(), value)).collect();
+            assert_eq!(key.values().unwrap(), expected);
+            assert_eq!(key.value_names().unwrap(), expected.iter().map(|(name, _)|name.clone()).collect::<Vec<_>>());
+            assert_eq!(key.query_value("big").unwrap(), big_value());
+            assert_eq!(key.query_value("ÜNÏCØDÉ ✓").unwrap(), ValueBuf::Qword(1 << 40));
+            assert_eq!(key.query_value("").unwrap(), ValueBuf::from("default"));
+            assert_eq!(key.query_value("missing").unwrap_err(), Error{code:ERROR_FILE_NOT_FOUND});
+
+            let info = key.info().unwrap();
+            assert_eq!((info.subkeys, info.values), (0, 6));
+            assert_eq!(info.max_value_name_len, 9);
+            assert_eq!(info.max_value_len, 40000);
+        }
+    }
+
+    #[test]
+    fn read_only() {
+        let hive = Hive::from_bytes(system_hive(5)).unwrap();
+        let key = hello(&hive);
+        let denied = Error{code:ERROR_ACCESS_DENIED};
+        assert_eq!(key.put_value("Start", &ValueBuf::Dword(3)).unwrap_err(), denied);
+        assert_eq!(key.remove_value("Start").unwrap_err(), denied);
+        assert_eq!(key.create_subkey("Configuration").unwrap_err(), denied);
+        assert_eq!(key.remove_subtree("Configuration").unwrap_err(), denied);
+        assert_eq!(key.query_value("Start").unwrap(), ValueBuf::Dword(2));
+    }
+
+    #[test]
+    fn export() {
+        // the same keys exported from the hive and from memory
+        let hive = Hive::from_bytes(system_hive(5)).unwrap();
+        let path = "HKEY_LOCAL_MACHINE\\SYSTEM\\ControlSet001\\Services\\hello";
+        let exported = RegFile::export(&hello(&hive), path).unwrap();
+        let memory = MemoryKey::new();
+        exported.apply(&memory, path).unwrap();
+        assert_eq!(RegFile::export(&memory, path).unwrap().to_string(), exported.to_string());
+        assert_eq!(memory.open("Parameters").unwrap().query_value("Big").unwrap(), big_value());
+    }
+
+    #[cfg(feature = "serde_config")]
+    #[test]
+    fn deserialize() {
+        use crate::serde_config::Deserializer;
+        use serde::Deserialize;
+
+        #[derive(Deserialize, Debug, Eq, PartialEq)]
+        struct Limits {
+            max_connections: u16,
+        }
+
+        #[derive(Deserialize, Debug, Eq, PartialEq)]
+        struct Configuration {
+            listen_port: u32,
+            name: String,
+            greeting: Option<String>,
+            limits: Limits,
+        }
+
+        let hive = Hive::from_bytes(system_hive(5)).unwrap();
+        let mut deserializer = Deserializer::new(hello(&hive), "Configuration".into());
+        assert_eq!(Configuration::deserialize(&mut deserializer).unwrap(), Configuration{
+            listen_port: 8080,
+            name: "hello".into(),
+            greeting: None,
+            limits: Limits{ max_connections: 16 },
+        });
+    }
+
+    #[test]
+    fn corrupt() {
+        let bad = Err(Error{code:ERROR_BADDB});
+        let bytes = system_hive(5);
+        assert_eq!(Hive::from_bytes(bytes[..BASE_BLOCK_LEN].to_vec()).map(|_|()), bad);
+        let mut wrong_signature = bytes.clone();
+        put(&mut wrong_signature, 0, b"regg");
+        assert_eq!(Hive::from_bytes(wrong_signature).map(|_|()), bad);
+
+        // the root is written last, a copy cut short loses it
+        let mut dirty = bytes.clone();
+        put(&mut dirty, 0x04, &2u32.to_le_bytes());
+        let root = u32_at(&bytes, 0x24).unwrap() as usize;
+        let truncated = Hive::from_bytes(dirty[..BASE_BLOCK_LEN + root + 8].to_vec()).unwrap();
+        assert!(truncated.is_dirty());
+        assert_eq!(truncated.root().map(|_|()), bad);
+
+        // a subkey list pointing at the first cell, which is a value
+        let mut misplaced = bytes.clone();
+        let list = u32_at(&bytes, BASE_BLOCK_LEN + root + 4 + NK_SUBKEY_LIST).unwrap() as usize;
+        put(&mut misplaced, BASE_BLOCK_LEN + list + 4 + 4, &(HBIN_HEADER_LEN as u32).to_le_bytes());
+        let root = Hive::from_bytes(misplaced).unwrap().root().unwrap();
+        assert_eq!(root.open("ControlSet001").map(|_|()), bad);
+    }
+
+    /// a hive written by windows itself, from a key built for the test
+    #[cfg(windows)]
+    #[test]
+    fn reg_save() {
+        use crate::registry::{HKEY_CURRENT_USER, KeyExt};
+
+        let parent = HKEY_CURRENT_USER.create("SOFTWARE\\n8ware\\test\\winsvc").unwrap();
+        let _ = parent.delete_tree("hive");
+        let key = parent.create("hive").unwrap();
+        for (name, value) in parameters() {
+            key.set_value(name, &value).unwrap();
+        }
+        key.create("Configuration\\limits").unwrap().set_value("max_connections", &ValueBuf::Dword(16)).unwrap();
+        for name in ["alpha", "beta", "Ωmega"] {
+            key.create(name).unwrap();
+        }
+
+        let file = std::env::temp_dir().join(format!("winsvc-test-{}.hiv", std::process::id()));
+        let status = std::process::Command::new("reg")
+            .args(["save", "HKCU\\SOFTWARE\\n8ware\\test\\winsvc\\hive"])
+            .arg(&file)
+            .arg("/y")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let bytes = std::fs::read(&file).unwrap();
+        let _ = std::fs::remove_file(&file);
+
+        let hive = Hive::from_bytes(bytes).unwrap();
+        let root = hive.root().unwrap();
+        assert_eq!(root.query_value("big").unwrap(), big_value());
+        assert_eq!(root.open("CONFIGURATION\\Limits").unwrap().query_value("max_connections").unwrap(), ValueBuf::Dword(16));
+        let path = "HKEY_CURRENT_USER\\SOFTWARE\\n8ware\\test\\winsvc\\hive";
+        assert_eq!(RegFile::export(&root, path).unwrap().to_string(), RegFile::export(&key, path).unwrap().to_string());
+    }
+
+    #[test]
+    fn case_folding() {
+        let mut builder = Builder::new(5);
+        let street = builder.key("Straße", "", &[("Ärger", ValueBuf::Dword(1))], List::Lf, &[]);
+        let root = builder.key("ROOT", "", &[], List::Lf, &[street]);
+        let root = Hive::from_bytes(builder.finish(root)).unwrap().root().unwrap();
+
+        let street = root.open("STRAßE").unwrap();
+        assert_eq!(street.query_value("ärger").unwrap(), ValueBuf::Dword(1));
+        // the registry does not expand ß to SS
+        assert_eq!(root.open("STRASSE").map(|_|()), Err(Error{code:ERROR_FILE_NOT_FOUND}));
+    }
+
+    #[test]
+    fn cycle() {
+        let mut builder = Builder::new(5);
+        let child = builder.key("child", "", &[], List::Lf, &[]);
+        let root = builder.key("ROOT", "", &[], List::Lf, &[child]);
+        let mut bytes = builder.finish(root);
+
+        // the child lists itself through the root's subkey list
+        let list = u32_at(&bytes, BASE_BLOCK_LEN + root as usize + 4 + NK_SUBKEY_LIST).unwrap();
+        put(&mut bytes, BASE_BLOCK_LEN + child as usize + 4 + NK_SUBKEY_COUNT, &1u32.to_le_bytes());
+        put(&mut bytes, BASE_BLOCK_LEN + child as usize + 4 + NK_SUBKEY_LIST, &list.to_le_bytes());
+
+        let root = Hive::from_bytes(bytes).unwrap().root().unwrap();
+        assert!(root.open("child\\child\\child").is_ok());
+        assert!(root.open(&["child"; 512].join("\\")).is_ok());
+        assert_eq!(root.open(&["child"; 513].join("\\")).map(|_|()), Err(Error{code:ERROR_BADDB}));
+        assert_eq!(RegFile::export(&root, "HKEY_LOCAL_MACHINE\\SYSTEM").map(|_|()), Err(Error{code:ERROR_BADDB}));
+    }
+
+    /// read every key and value down to `depth`, a corrupt hive may have
+    /// cycles
+    fn walk(key: &HiveKey, depth: usize) {
+        let _ = key.info();
+        let _ = key.values();
+        if depth > 0 {
+            for index in 0..64 {
+                match key.subkey(index) {
+                    Ok(Some(subkey)) => walk(&subkey, depth - 1),
+                    _ => break,
+                }
+            }
+        }
+    }
+
+    quickcheck! {
+        fn corruption_is_an_error(changes: Vec<(u16, u8)>) -> bool {
+            let mut bytes = system_hive(5);
+            let len = bytes.len() - BASE_BLOCK_LEN;
+            for (pos, byte) in changes {
+                bytes[BASE_BLOCK_LEN + pos as usize % len] = byte;
+            }
+            if let Ok(root) = Hive::from_bytes(bytes).and_then(|hive|hive.root()) {
+                walk(&root, 6);
+            }
+            true
+        }
+    }
+}
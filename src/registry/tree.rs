@@ -0,0 +1,67 @@
+//! Navigation and editing shared by every kind of key
+
+use super::{ValueBuf, MemoryKey};
+use crate::Result;
+
+/// the operations common to `Key`, `MemoryKey` and `HiveKey`, used by code
+/// such as the `.reg` import and export that works with any of them
+///
+/// Names are `&str` and paths separate keys with `\`.
+pub trait KeyTree: Sized {
+    fn open_subkey(&self, path: &str) -> Result<Self>;
+    /// create a new or open an existing subkey, `""` opens this key again
+    fn create_subkey(&self, path: &str) -> Result<Self>;
+    fn subkey_names(&self) -> Result<Vec<String>>;
+    /// the values in the order they are enumerated
+    fn values(&self) -> Result<Vec<(String,ValueBuf)>>;
+    /// the names of the values, without reading their data
+    fn value_names(&self) -> Result<Vec<String>> {
+        Ok(self.values()?.into_iter().map(|(name, _)|name).collect())
+    }
+    fn get_value(&self, name: &str) -> Result<ValueBuf>;
+    fn put_value(&self, name: &str, value: &ValueBuf) -> Result<()>;
+    fn remove_value(&self, name: &str) -> Result<()>;
+    fn remove_subtree(&self, path: &str) -> Result<()>;
+}
+
+impl KeyTree for MemoryKey {
+    fn open_subkey(&self, path: &str) -> Result<Self> { self.open(path) }
+    fn create_subkey(&self, path: &str) -> Result<Self> { self.create(path) }
+    fn subkey_names(&self) -> Result<Vec<String>> { self.iter_key_names().collect() }
+    fn values(&self) -> Result<Vec<(String,ValueBuf)>> { self.iter_values().collect() }
+    fn value_names(&self) -> Result<Vec<String>> { self.iter_value_names().collect() }
+    fn get_value(&self, name: &str) -> Result<ValueBuf> { self.query_value(name) }
+    fn put_value(&self, name: &str, value: &ValueBuf) -> Result<()> { self.set_value(name, value) }
+    fn remove_value(&self, name: &str) -> Result<()> { self.delete_value(name) }
+    fn remove_subtree(&self, path: &str) -> Result<()> { self.delete_tree(path) }
+}
+
+#[cfg(windows)]
+impl KeyTree for super::Key {
+    fn open_subkey(&self, path: &str) -> Result<Self> { self.open(path) }
+
+    fn create_subkey(&self, path: &str) -> Result<Self> {
+        use super::{OpenOptions, KeyAccess};
+        OpenOptions::new().access(KeyAccess::READ | KeyAccess::WRITE).create(self, path).map(|(key, _)|key)
+    }
+
+    fn subkey_names(&self) -> Result<Vec<String>> {
+        use super::KeyExt;
+        self.iter_key_names().map(|name|name.map(|name|name.to_string_lossy().into_owned())).collect()
+    }
+
+    fn values(&self) -> Result<Vec<(String,ValueBuf)>> {
+        use super::KeyExt;
+        self.iter_values().map(|value|value.map(|(name, value)|(name.to_string_lossy().into_owned(), value))).collect()
+    }
+
+    fn value_names(&self) -> Result<Vec<String>> {
+        use super::KeyExt;
+        self.iter_value_names().map(|name|name.map(|name|name.to_string_lossy().into_owned())).collect()
+    }
+
+    fn get_value(&self, name: &str) -> Result<ValueBuf> { super::KeyExt::query_value(self, name) }
+    fn put_value(&self, name: &str, value: &ValueBuf) -> Result<()> { super::KeyExt::set_value(self, name, value) }
+    fn remove_value(&self, name: &str) -> Result<()> { super::KeyExt::delete_value(self, name) }
+    fn remove_subtree(&self, path: &str) -> Result<()> { super::KeyExt::delete_tree(self, path) }
+}
@@ -1,56 +1,187 @@
+//! Registry values and their byte layouts
+//!
+//! Strings are held without their terminating NUL, `encode` adds it back. On
+//! decode a string ends at its first NUL, or at the end of the data if it has
+//! none, as the registry does not enforce termination.
 
 use std::{
-    ffi::OsString,
     convert::TryFrom,
-    os::windows::ffi::OsStringExt,
-    mem::size_of,
- };
-use super::{Result,to_wstr};
-use winapi::{
-    shared::winerror::ERROR_INVALID_DATA,
-    um::winnt::{
-        REG_SZ,
-        REG_BINARY,
-        REG_DWORD,
-        REG_QWORD,
-    },
+    iter::FromIterator,
 };
 
+#[cfg(windows)]
+use std::{
+    ffi::{OsStr,OsString},
+    os::windows::ffi::{OsStrExt,OsStringExt},
+};
+
+use crate::{Error,Result};
+
+pub const REG_NONE: u32 = 0;
+pub const REG_SZ: u32 = 1;
+pub const REG_EXPAND_SZ: u32 = 2;
+pub const REG_BINARY: u32 = 3;
+pub const REG_DWORD: u32 = 4;
+pub const REG_DWORD_BIG_ENDIAN: u32 = 5;
+pub const REG_LINK: u32 = 6;
+pub const REG_MULTI_SZ: u32 = 7;
+pub const REG_RESOURCE_LIST: u32 = 8;
+pub const REG_FULL_RESOURCE_DESCRIPTOR: u32 = 9;
+pub const REG_RESOURCE_REQUIREMENTS_LIST: u32 = 10;
+pub const REG_QWORD: u32 = 11;
+
+// ERROR_INVALID_DATA, spelled out so decoding works on every platform
+const DATA_ERR : Error = Error{code:13};
+
 #[derive(Clone,Debug,Eq,PartialEq)]
 pub enum ValueBuf {
+    /// no defined type, the data is kept as is
+    None(Vec<u8>),
     String(Vec<u16>),
+    /// a string with `%VARIABLE%` references to expand
+    ExpandString(Vec<u16>),
     Binary(Vec<u8>),
     Dword(u32),
+    DwordBigEndian(u32),
+    /// the target of a symbolic link, a native path stored without a NUL
+    Link(Vec<u16>),
+    MultiString(Vec<Vec<u16>>),
+    /// hardware resources, opaque
+    ResourceList(Vec<u8>),
+    FullResourceDescriptor(Vec<u8>),
+    ResourceRequirementsList(Vec<u8>),
     Qword(u64),
+    /// a type not known here
+    Other{value_type: u32, data: Vec<u8>},
+}
+
+fn wide_to_bytes(wide: &[u16], bytes: &mut Vec<u8>) {
+    for c in wide {
+        bytes.extend_from_slice(&c.to_le_bytes());
+    }
+}
+
+/// a trailing odd byte is not part of any character and is dropped
+fn bytes_to_wide(bytes: &[u8]) -> Vec<u16> {
+    bytes.chunks_exact(2).map(|c|u16::from_le_bytes([c[0], c[1]])).collect()
+}
+
+fn until_nul(wide: &[u16]) -> &[u16] {
+    match wide.iter().position(|c|*c == 0) {
+        Some(end) => &wide[..end],
+        None => wide,
+    }
+}
+
+fn string_bytes(string: &[u16]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(string.len() * 2 + 2);
+    wide_to_bytes(string, &mut bytes);
+    bytes.extend_from_slice(&[0,0]);
+    bytes
 }
 
 impl ValueBuf {
-    pub fn as_string(&self) -> Result<OsString> {
+    /// the registry type, one of the `REG_*` constants
+    pub fn value_type(&self) -> u32 {
+        match self {
+            Self::None(_) => REG_NONE,
+            Self::String(_) => REG_SZ,
+            Self::ExpandString(_) => REG_EXPAND_SZ,
+            Self::Binary(_) => REG_BINARY,
+            Self::Dword(_) => REG_DWORD,
+            Self::DwordBigEndian(_) => REG_DWORD_BIG_ENDIAN,
+            Self::Link(_) => REG_LINK,
+            Self::MultiString(_) => REG_MULTI_SZ,
+            Self::ResourceList(_) => REG_RESOURCE_LIST,
+            Self::FullResourceDescriptor(_) => REG_FULL_RESOURCE_DESCRIPTOR,
+            Self::ResourceRequirementsList(_) => REG_RESOURCE_REQUIREMENTS_LIST,
+            Self::Qword(_) => REG_QWORD,
+            Self::Other{value_type, ..} => *value_type,
+        }
+    }
+
+    /// the data as stored in the registry
+    pub fn encode(&self) -> Vec<u8> {
         match self {
-            Self::String(string) => Ok(OsString::from_wide(string)),
-            _ => panic!()
+            Self::String(string) | Self::ExpandString(string) => string_bytes(string),
+            Self::Link(target) => {
+                let mut bytes = Vec::with_capacity(target.len() * 2);
+                wide_to_bytes(target, &mut bytes);
+                bytes
+            },
+            Self::MultiString(strings) => {
+                let mut bytes = Vec::new();
+                for string in strings {
+                    bytes.extend(string_bytes(string));
+                }
+                // the list ends with an empty string, alone if the list is empty
+                bytes.extend_from_slice(&[0,0]);
+                bytes
+            },
+            Self::Dword(value) => value.to_le_bytes().to_vec(),
+            Self::DwordBigEndian(value) => value.to_be_bytes().to_vec(),
+            Self::Qword(value) => value.to_le_bytes().to_vec(),
+            Self::None(data)
+            | Self::Binary(data)
+            | Self::ResourceList(data)
+            | Self::FullResourceDescriptor(data)
+            | Self::ResourceRequirementsList(data)
+            | Self::Other{data, ..} => data.clone(),
         }
     }
 
-    pub(super) fn type_ptr_len(&self) -> Result<(u32, (*const u8, u32))> {
-        fn slice_ptr_len<T:Sized>(v: &[T]) -> (*const u8, u32) {
-            (v.as_ptr() as *const _, (v.len()*size_of::<T>()) as u32)
+    /// interpret data read from the registry
+    pub fn decode(value_type: u32, data: &[u8]) -> Result<Self> {
+        fn fixed<const N: usize>(data: &[u8]) -> Result<[u8;N]> {
+            <[u8;N]>::try_from(data).map_err(|_|DATA_ERR)
         }
-        fn any_ptr_len<T:Sized>(v: &T) -> (*const u8, u32) {
-            (v as *const _ as *const _, size_of::<T>() as u32)
+
+        Ok(match value_type {
+            REG_NONE => Self::None(data.into()),
+            REG_SZ => Self::String(until_nul(&bytes_to_wide(data)).into()),
+            REG_EXPAND_SZ => Self::ExpandString(until_nul(&bytes_to_wide(data)).into()),
+            REG_BINARY => Self::Binary(data.into()),
+            REG_DWORD => Self::Dword(u32::from_le_bytes(fixed(data)?)),
+            REG_DWORD_BIG_ENDIAN => Self::DwordBigEndian(u32::from_be_bytes(fixed(data)?)),
+            REG_LINK => Self::Link(bytes_to_wide(data)),
+            REG_MULTI_SZ => Self::MultiString(
+                bytes_to_wide(data)
+                    .split(|c|*c == 0)
+                    // the list ends at the first empty string
+                    .take_while(|string|!string.is_empty())
+                    .map(Vec::from)
+                    .collect()
+            ),
+            REG_RESOURCE_LIST => Self::ResourceList(data.into()),
+            REG_FULL_RESOURCE_DESCRIPTOR => Self::FullResourceDescriptor(data.into()),
+            REG_RESOURCE_REQUIREMENTS_LIST => Self::ResourceRequirementsList(data.into()),
+            REG_QWORD => Self::Qword(u64::from_le_bytes(fixed(data)?)),
+            value_type => Self::Other{value_type, data: data.into()},
+        })
+    }
+
+    #[cfg(windows)]
+    pub fn as_string(&self) -> Result<OsString> {
+        match self {
+            Self::String(string) | Self::ExpandString(string) => Ok(OsString::from_wide(string)),
+            _ => Err(DATA_ERR),
         }
+    }
+
+    #[cfg(windows)]
+    pub fn as_strings(&self) -> Result<Vec<OsString>> {
         match self {
-           ValueBuf::String(string) => Ok((REG_SZ, slice_ptr_len(string))),
-           ValueBuf::Binary(value) => Ok((REG_BINARY, slice_ptr_len(value))),
-           ValueBuf::Dword(value) => Ok((REG_DWORD, any_ptr_len(value))),
-           ValueBuf::Qword(value) => Ok((REG_QWORD, any_ptr_len(value))),
+            Self::MultiString(strings) => Ok(strings.iter().map(|string|OsString::from_wide(string)).collect()),
+            _ => Err(DATA_ERR),
         }
     }
 
+    #[cfg(windows)]
+    pub fn expand_string(string: impl AsRef<OsStr>) -> Self {
+        Self::ExpandString(string.as_ref().encode_wide().collect())
+    }
 }
 
-const DATA_ERR : crate::Error = crate::Error{code:ERROR_INVALID_DATA};
-
 macro_rules! u32s {
     ( $( $t:ty ),+ $(,)? ) => { $(
 
@@ -63,10 +194,9 @@ macro_rules! u32s {
         impl TryFrom<ValueBuf> for $t {
             type Error = crate::Error;
             fn try_from(value: ValueBuf) -> Result<Self> {
-                if let ValueBuf::Dword(value) = value {
-                    Ok(value as $t)
-                } else {
-                    Err(DATA_ERR)
+                match value {
+                    ValueBuf::Dword(value) | ValueBuf::DwordBigEndian(value) => Ok(value as $t),
+                    _ => Err(DATA_ERR),
                 }
             }
         }
@@ -126,23 +256,32 @@ impl From<char> for ValueBuf {
 
 impl From<&str> for ValueBuf {
     fn from(value: &str) -> Self {
-        Self::String(to_wstr(value))
+        Self::String(value.encode_utf16().collect())
     }
 }
 
 impl From<String> for ValueBuf {
     fn from(value: String) -> Self {
-        Self::String(to_wstr(value))
+        Self::from(value.as_str())
     }
 }
 
 impl TryFrom<ValueBuf> for String {
     type Error = crate::Error;
     fn try_from(value: ValueBuf) -> Result<Self> {
-        if let ValueBuf::String(value) = value {
-            Ok(OsString::from_wide(&value).into_string().map_err(|_|DATA_ERR)?)
-        } else {
-            Err(DATA_ERR)
+        match value {
+            ValueBuf::String(value) | ValueBuf::ExpandString(value) => String::from_utf16(&value).map_err(|_|DATA_ERR),
+            _ => Err(DATA_ERR),
+        }
+    }
+}
+
+impl TryFrom<ValueBuf> for Vec<String> {
+    type Error = crate::Error;
+    fn try_from(value: ValueBuf) -> Result<Self> {
+        match value {
+            ValueBuf::MultiString(strings) => strings.iter().map(|string|String::from_utf16(string).map_err(|_|DATA_ERR)).collect(),
+            _ => Err(DATA_ERR),
         }
     }
 }
@@ -158,3 +297,78 @@ impl From<Vec<u8>> for ValueBuf {
         Self::Binary(value)
     }
 }
+
+impl<S: AsRef<str>> FromIterator<S> for ValueBuf {
+    /// collect strings into a `MultiString`
+    fn from_iter<I: IntoIterator<Item=S>>(strings: I) -> Self {
+        Self::MultiString(strings.into_iter().map(|string|string.as_ref().encode_utf16().collect()).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn wide(string: &str) -> Vec<u16> {
+        string.encode_utf16().collect()
+    }
+
+    fn round_trip(value: ValueBuf, bytes: &[u8]) {
+        assert_eq!(value.encode(), bytes, "encoding {:?}", value);
+        assert_eq!(ValueBuf::decode(value.value_type(), bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn strings() {
+        round_trip(ValueBuf::String(wide("ab")), b"a\0b\0\0\0");
+        round_trip(ValueBuf::String(Vec::new()), b"\0\0");
+        round_trip(ValueBuf::ExpandString(wide("%T%")), b"%\0T\0%\0\0\0");
+        round_trip(ValueBuf::Link(wide("\\R")), b"\\\0R\0");
+    }
+
+    #[test]
+    fn strings_without_nul() {
+        assert_eq!(ValueBuf::decode(REG_SZ, b"a\0b\0").unwrap(), ValueBuf::String(wide("ab")));
+        assert_eq!(ValueBuf::decode(REG_SZ, b"a\0\0\0b\0").unwrap(), ValueBuf::String(wide("a")));
+        assert_eq!(ValueBuf::decode(REG_EXPAND_SZ, b"a\0b").unwrap(), ValueBuf::ExpandString(wide("a")));
+    }
+
+    #[test]
+    fn multi_strings() {
+        round_trip(ValueBuf::MultiString(vec![wide("a"), wide("bc")]), b"a\0\0\0b\0c\0\0\0\0\0");
+        round_trip(ValueBuf::MultiString(Vec::new()), b"\0\0");
+        assert_eq!(ValueBuf::decode(REG_MULTI_SZ, b"").unwrap(), ValueBuf::MultiString(Vec::new()));
+        // missing terminators and data after the end of the list
+        assert_eq!(ValueBuf::decode(REG_MULTI_SZ, b"a\0\0\0b\0").unwrap(), ValueBuf::MultiString(vec![wide("a"), wide("b")]));
+        assert_eq!(ValueBuf::decode(REG_MULTI_SZ, b"a\0\0\0\0\0b\0").unwrap(), ValueBuf::MultiString(vec![wide("a")]));
+        assert_eq!(vec!["a", "bc"].into_iter().collect::<ValueBuf>(), ValueBuf::MultiString(vec![wide("a"), wide("bc")]));
+    }
+
+    #[test]
+    fn numbers() {
+        round_trip(ValueBuf::Dword(0x12345678), &[0x78, 0x56, 0x34, 0x12]);
+        round_trip(ValueBuf::DwordBigEndian(0x12345678), &[0x12, 0x34, 0x56, 0x78]);
+        round_trip(ValueBuf::Qword(0x0102030405060708), &[8, 7, 6, 5, 4, 3, 2, 1]);
+        assert!(ValueBuf::decode(REG_DWORD, &[1, 2, 3]).is_err());
+        assert!(ValueBuf::decode(REG_QWORD, &[1, 2, 3, 4]).is_err());
+    }
+
+    #[test]
+    fn opaque() {
+        round_trip(ValueBuf::None(vec![1, 2]), &[1, 2]);
+        round_trip(ValueBuf::None(Vec::new()), &[]);
+        round_trip(ValueBuf::Binary(vec![0, 255]), &[0, 255]);
+        round_trip(ValueBuf::ResourceList(vec![1]), &[1]);
+        round_trip(ValueBuf::FullResourceDescriptor(vec![2]), &[2]);
+        round_trip(ValueBuf::ResourceRequirementsList(vec![3]), &[3]);
+        round_trip(ValueBuf::Other{value_type: 0x1234, data: vec![4]}, &[4]);
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(String::try_from(ValueBuf::from("hello")).unwrap(), "hello");
+        assert_eq!(u32::try_from(ValueBuf::DwordBigEndian(7)).unwrap(), 7);
+        assert_eq!(Vec::<String>::try_from(vec!["a", "b"].into_iter().collect::<ValueBuf>()).unwrap(), vec!["a", "b"]);
+        assert!(String::try_from(ValueBuf::Dword(1)).is_err());
+    }
+}
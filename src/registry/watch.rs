@@ -0,0 +1,290 @@
+//! Registry change notifications
+//!
+//! `RegNotifyChangeKeyValue` signals an event when the key changes, but the
+//! registration ends when the thread that made it exits. As with the service
+//! notifications each watch owns a worker thread that registers, waits
+//! alertably on the event and hands each change to a runtime agnostic future.
+
+use std::{
+    ptr::null_mut,
+    marker::PhantomData,
+    task::{Context,Poll},
+    sync::atomic::{AtomicBool,Ordering::SeqCst},
+};
+
+use winapi::{
+    shared::minwindef::{HKEY,TRUE,FALSE},
+    um::{
+        handleapi::CloseHandle,
+        synchapi::{CreateEventW,WaitForSingleObjectEx},
+        winbase::{INFINITE,WAIT_OBJECT_0,WAIT_IO_COMPLETION},
+        winnt::{
+            REG_NOTIFY_CHANGE_NAME,
+            REG_NOTIFY_CHANGE_ATTRIBUTES,
+            REG_NOTIFY_CHANGE_LAST_SET,
+            REG_NOTIFY_CHANGE_SECURITY,
+        },
+        winreg::RegNotifyChangeKeyValue,
+    },
+};
+
+use crate::service_notify::{Shared,Worker,shared,push,poll_pop};
+
+use super::{Key, Error, Result};
+
+bit_flags!{
+    /// the changes `Key::watch` reports
+    ChangeFilter {
+        /// a subkey was added or deleted
+        NAME = REG_NOTIFY_CHANGE_NAME,
+        ATTRIBUTES = REG_NOTIFY_CHANGE_ATTRIBUTES,
+        /// a value was added, deleted or changed
+        LAST_SET = REG_NOTIFY_CHANGE_LAST_SET,
+        SECURITY = REG_NOTIFY_CHANGE_SECURITY,
+    }
+}
+
+/// handles are only used by the worker while the owner borrows them
+#[derive(Copy,Clone)]
+struct SendKey(HKEY);
+
+unsafe impl Send for SendKey {}
+
+/// call `changed` each time `key` changes until `stop` is set or it returns
+/// false, errors end the watch
+unsafe fn watch_key(
+    key: HKEY,
+    filter: ChangeFilter,
+    recursive: bool,
+    stop: &AtomicBool,
+    mut changed: impl FnMut(Result<()>) -> bool,
+) {
+    let event = CreateEventW(null_mut(), FALSE, FALSE, null_mut());
+    if event.is_null() {
+        changed(Err(Error::from_last()));
+        return;
+    }
+
+    'watch: loop {
+        let registered = Error::check_code(RegNotifyChangeKeyValue(
+            /* hKey           */ key,
+            /* bWatchSubtree  */ if recursive { TRUE } else { FALSE },
+            /* dwNotifyFilter */ filter.into_raw(),
+            /* hEvent         */ event,
+            /* fAsynchronous  */ TRUE,
+        ));
+        if let Err(error) = registered {
+            changed(Err(error));
+            break;
+        }
+
+        loop {
+            if stop.load(SeqCst) {
+                break 'watch;
+            }
+            match WaitForSingleObjectEx(event, INFINITE, TRUE) {
+                WAIT_OBJECT_0 => break,
+                // woken by an APC, possibly to stop
+                WAIT_IO_COMPLETION => continue,
+                _ => {
+                    changed(Err(Error::from_last()));
+                    break 'watch;
+                },
+            }
+        }
+
+        if !changed(Ok(())) {
+            break;
+        }
+    }
+
+    CloseHandle(event);
+}
+
+/// stream of changes to a key
+///
+/// The first error, e.g. `ERROR_KEY_DELETED` once the key is deleted, ends
+/// the watch: it is reported and then `next` returns `None`. With the
+/// `stream` feature this is a `futures_core::Stream`, which `tokio_stream`'s
+/// `StreamExt` works with; without it `futures::stream::poll_fn(|cx|
+/// changes.poll_next(cx))` adapts it.
+pub struct KeyChanges<'a> {
+    _key: PhantomData<&'a Key>,
+    shared: Shared<Result<()>>,
+    _worker: Worker,
+}
+
+impl<'a> KeyChanges<'a> {
+    /// wait for the next change, changes made while nobody is waiting are
+    /// queued, `None` once the watch has ended
+    pub async fn next(&mut self) -> Option<Result<()>> {
+        std::future::poll_fn(|cx|self.poll_next(cx)).await
+    }
+
+    /// poll for the next change as `Stream::poll_next` does
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<()>>> {
+        poll_pop(&self.shared, cx)
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<'a> futures_core::Stream for KeyChanges<'a> {
+    type Item = Result<()>;
+
+    fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<()>>> {
+        KeyChanges::poll_next(self.get_mut(), cx)
+    }
+}
+
+impl Key {
+    /// receive a notification each time the key, or with `recursive` any of
+    /// its subkeys, changes as selected by `filter`
+    ///
+    /// Requires `KeyAccess::NOTIFY`, which `KeyAccess::READ` includes.
+    pub fn watch(&self, filter: ChangeFilter, recursive: bool) -> KeyChanges<'_> {
+        let (shared, sender) = shared();
+        let key = SendKey(self.0);
+        let worker = Worker::spawn(move |stop| {
+            let key = key;
+            unsafe {
+                watch_key(key.0, filter, recursive, stop, |result| {
+                    let more = result.is_ok();
+                    push(&sender, result);
+                    more
+                })
+            }
+        });
+        KeyChanges{ _key: PhantomData, shared, _worker: worker }
+    }
+}
+
+/// keeps an object stored in the registry loaded, see `Key::watch_object`
+#[cfg(all(feature = "serde_config", feature = "async_main"))]
+pub struct ObjectWatch<C> {
+    receiver: tokio::sync::watch::Receiver<C>,
+    // fields drop in order, the worker must be joined before the key closes
+    _worker: Worker,
+    _key: Key,
+}
+
+#[cfg(all(feature = "serde_config", feature = "async_main"))]
+impl<C> ObjectWatch<C> {
+    /// a receiver of the current object and every reload
+    pub fn subscribe(&self) -> tokio::sync::watch::Receiver<C> {
+        self.receiver.clone()
+    }
+}
+
+#[cfg(all(feature = "serde_config", feature = "async_main"))]
+impl Key {
+    /// load the object stored under `name` and load it again each time this
+    /// key or a subkey changes, until the returned watch is dropped
+    ///
+    /// A reload that fails, e.g. because the object is half written, is
+    /// logged and the previous object kept.
+    pub fn watch_object<C>(self, name: &str) -> Result<ObjectWatch<C>> where
+        C: serde::de::DeserializeOwned + Send + Sync + 'static,
+    {
+        use super::{KeyExt,KeyRef};
+
+        let (sender, receiver) = tokio::sync::watch::channel(self.query_object::<C>(name)?);
+
+        let name = name.to_owned();
+        let key = SendKey(self.0);
+        let worker = Worker::spawn(move |stop| {
+            let key = key;
+            let filter = ChangeFilter::NAME | ChangeFilter::LAST_SET;
+            unsafe {
+                watch_key(key.0, filter, true, stop, |result| {
+                    if let Err(error) = result {
+                        tracing::error!("watching {:?} failed: {:?}", name, error);
+                        return false;
+                    }
+                    match KeyRef(key.0, PhantomData).query_object::<C>(name.as_str()) {
+                        Ok(object) => sender.send(object).is_ok(),
+                        Err(error) => {
+                            tracing::warn!("reloading {:?} failed: {:?}", name, error);
+                            true
+                        },
+                    }
+                })
+            }
+        });
+
+        Ok(ObjectWatch{ receiver, _key: self, _worker: worker })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::registry::{HKEY_CURRENT_USER, KeyExt, ValueBuf};
+    use winapi::shared::winerror::ERROR_KEY_DELETED;
+    use std::{
+        future::Future,
+        sync::Arc,
+        task::Wake,
+        thread,
+        time::Duration,
+    };
+
+    struct Unpark(thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) { self.0.unpark() }
+    }
+
+    fn block_on<T>(future: impl Future<Output=T>) -> T {
+        let waker = Arc::new(Unpark(thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn set_value_wakes_watch() {
+        let key = HKEY_CURRENT_USER.create("SOFTWARE\\n8ware\\test\\winsvc\\watch").unwrap();
+        let mut changes = key.watch(ChangeFilter::LAST_SET, false);
+
+        // the worker registers in the background, keep writing until it sees one
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let key = HKEY_CURRENT_USER.create("SOFTWARE\\n8ware\\test\\winsvc\\watch").unwrap();
+                let mut count = 0u32;
+                while !stop.load(SeqCst) {
+                    count += 1;
+                    key.set_value("count", &ValueBuf::Dword(count)).unwrap();
+                    thread::sleep(Duration::from_millis(10));
+                }
+            })
+        };
+
+        let result = block_on(changes.next());
+        stop.store(true, SeqCst);
+        writer.join().unwrap();
+        result.unwrap().unwrap();
+    }
+
+    #[test]
+    fn deleted_key_ends_watch() {
+        let parent = HKEY_CURRENT_USER.create("SOFTWARE\\n8ware\\test\\winsvc").unwrap();
+        let key = parent.create("watch_deleted").unwrap();
+        let mut changes = key.watch(ChangeFilter::NAME | ChangeFilter::LAST_SET, false);
+        parent.delete_tree("watch_deleted").unwrap();
+
+        // a change may be seen before the registration fails on the deleted key
+        let mut last = None;
+        while let Some(change) = block_on(changes.next()) {
+            last = Some(change);
+        }
+        assert_eq!(last, Some(Err(Error{code:ERROR_KEY_DELETED})));
+        assert_eq!(block_on(changes.next()), None);
+    }
+}
@@ -0,0 +1,722 @@
+//! Security Descriptor Definition Language
+//!
+//! A portable parser and formatter for the SDDL strings windows uses to
+//! describe security descriptors, e.g. `O:SYG:SYD:(A;;CCLCSWRPWPDTLOCRRC;;;SY)`.
+//! The conversion to and from the binary form is left to the system, see
+//! `Service::security` and `Service::set_security`.
+
+use std::{fmt, str::FromStr};
+
+/// a parsed security descriptor, absent parts are neither read nor written
+#[derive(Clone,Debug,Default,Eq,PartialEq)]
+pub struct SecurityDescriptor {
+    pub owner: Option<Trustee>,
+    pub group: Option<Trustee>,
+    pub dacl: Option<Acl>,
+    pub sacl: Option<Acl>,
+}
+
+/// an access control list
+#[derive(Clone,Debug,Default,Eq,PartialEq)]
+pub struct Acl {
+    pub flags: AclFlags,
+    pub aces: Vec<Ace>,
+}
+
+#[derive(Copy,Clone,Debug,Default,Eq,PartialEq)]
+pub struct AclFlags {
+    /// `P`, inheritable ACEs from parents are ignored
+    pub protected: bool,
+    /// `AR`, inheritance to children is required
+    pub auto_inherit_req: bool,
+    /// `AI`, inheritance has been applied to children
+    pub auto_inherited: bool,
+    /// `NO_ACCESS_CONTROL`, a null ACL granting everyone full access
+    pub no_access_control: bool,
+}
+
+/// an access control entry
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub struct Ace {
+    pub ace_type: AceType,
+    pub flags: AceFlags,
+    pub rights: u32,
+    pub object_type: Option<String>,
+    pub inherit_object_type: Option<String>,
+    pub trustee: Trustee,
+    pub resource_attribute: Option<String>,
+}
+
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub enum AceType {
+    AccessAllowed,
+    AccessDenied,
+    ObjectAccessAllowed,
+    ObjectAccessDenied,
+    Audit,
+    Alarm,
+    ObjectAudit,
+    ObjectAlarm,
+    MandatoryLabel,
+    CallbackAccessAllowed,
+    CallbackAccessDenied,
+    CallbackAudit,
+    CallbackObjectAccessAllowed,
+    ResourceAttribute,
+    ScopedPolicyId,
+}
+
+const ACE_TYPES: &[(&str, AceType)] = &[
+    ("A", AceType::AccessAllowed),
+    ("D", AceType::AccessDenied),
+    ("OA", AceType::ObjectAccessAllowed),
+    ("OD", AceType::ObjectAccessDenied),
+    ("AU", AceType::Audit),
+    ("AL", AceType::Alarm),
+    ("OU", AceType::ObjectAudit),
+    ("OL", AceType::ObjectAlarm),
+    ("ML", AceType::MandatoryLabel),
+    ("XA", AceType::CallbackAccessAllowed),
+    ("XD", AceType::CallbackAccessDenied),
+    ("XU", AceType::CallbackAudit),
+    ("ZA", AceType::CallbackObjectAccessAllowed),
+    ("RA", AceType::ResourceAttribute),
+    ("SP", AceType::ScopedPolicyId),
+];
+
+/// inheritance and auditing flags of an ACE
+#[derive(Copy,Clone,Debug,Default,Eq,PartialEq)]
+pub struct AceFlags(pub u8);
+
+impl AceFlags {
+    pub const OBJECT_INHERIT: Self = Self(0x01);
+    pub const CONTAINER_INHERIT: Self = Self(0x02);
+    pub const NO_PROPAGATE_INHERIT: Self = Self(0x04);
+    pub const INHERIT_ONLY: Self = Self(0x08);
+    pub const INHERITED: Self = Self(0x10);
+    pub const SUCCESSFUL_ACCESS: Self = Self(0x40);
+    pub const FAILED_ACCESS: Self = Self(0x80);
+
+    pub const fn contains(self, other: Self) -> bool { self.0 & other.0 == other.0 }
+}
+
+impl std::ops::BitOr for AceFlags {
+    type Output = Self;
+    fn bitor(self, other: Self) -> Self { Self(self.0 | other.0) }
+}
+
+const ACE_FLAGS: &[(&str, AceFlags)] = &[
+    ("OI", AceFlags::OBJECT_INHERIT),
+    ("CI", AceFlags::CONTAINER_INHERIT),
+    ("NP", AceFlags::NO_PROPAGATE_INHERIT),
+    ("IO", AceFlags::INHERIT_ONLY),
+    ("ID", AceFlags::INHERITED),
+    ("SA", AceFlags::SUCCESSFUL_ACCESS),
+    ("FA", AceFlags::FAILED_ACCESS),
+];
+
+/// single bit access rights, in the order they are formatted
+const RIGHTS: &[(&str, u32)] = &[
+    ("GA", 0x1000_0000),
+    ("GR", 0x8000_0000),
+    ("GW", 0x4000_0000),
+    ("GX", 0x2000_0000),
+    ("CC", 0x0000_0001),
+    ("DC", 0x0000_0002),
+    ("LC", 0x0000_0004),
+    ("SW", 0x0000_0008),
+    ("RP", 0x0000_0010),
+    ("WP", 0x0000_0020),
+    ("DT", 0x0000_0040),
+    ("LO", 0x0000_0080),
+    ("CR", 0x0000_0100),
+    ("SD", 0x0001_0000),
+    ("RC", 0x0002_0000),
+    ("WD", 0x0004_0000),
+    ("WO", 0x0008_0000),
+];
+
+/// composite file, registry and label rights, accepted but never formatted
+const COMPOSITE_RIGHTS: &[(&str, u32)] = &[
+    ("FA", 0x001F_01FF),
+    ("FR", 0x0012_0089),
+    ("FW", 0x0012_0116),
+    ("FX", 0x0012_00A0),
+    ("KA", 0x000F_003F),
+    ("KR", 0x0002_0019),
+    ("KW", 0x0002_0006),
+    ("KX", 0x0002_0019),
+    ("NR", 0x0000_0002),
+    ("NW", 0x0000_0001),
+    ("NX", 0x0000_0004),
+];
+
+/// the account an ACE, owner or group refers to
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub enum Trustee {
+    /// a two letter well known alias, e.g. `SY` or `BA`
+    Alias(String),
+    /// a literal security identifier
+    Sid(Sid),
+}
+
+/// a security identifier, e.g. `S-1-5-32-544`
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub struct Sid {
+    pub revision: u8,
+    pub authority: u64,
+    pub sub_authorities: Vec<u32>,
+}
+
+/// the largest identifier authority, it is stored in 48 bits
+const MAX_AUTHORITY: u64 = 0xFFFF_FFFF_FFFF;
+
+/// the most sub authorities a SID may hold
+const MAX_SUB_AUTHORITIES: usize = 15;
+
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub struct ParseError {
+    /// byte offset into the input where parsing failed
+    pub position: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid SDDL at offset {}: {}", self.position, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub type ParseResult<T> = std::result::Result<T,ParseError>;
+
+impl Ace {
+    /// an ACE granting `rights` to `trustee`
+    pub fn allow(trustee: Trustee, rights: u32) -> Self {
+        Self::new(AceType::AccessAllowed, trustee, rights)
+    }
+
+    /// an ACE denying `rights` to `trustee`
+    pub fn deny(trustee: Trustee, rights: u32) -> Self {
+        Self::new(AceType::AccessDenied, trustee, rights)
+    }
+
+    fn new(ace_type: AceType, trustee: Trustee, rights: u32) -> Self {
+        Self{
+            ace_type,
+            flags: AceFlags::default(),
+            rights,
+            object_type: None,
+            inherit_object_type: None,
+            trustee,
+            resource_attribute: None,
+        }
+    }
+}
+
+/// cursor over the input string
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+
+    fn new(input: &'a str) -> Self {
+        Self{ input, position: 0 }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.position..]
+    }
+
+    fn error<T>(&self, message: &'static str) -> ParseResult<T> {
+        Err(ParseError{ position: self.position, message })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rest().is_empty()
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn eat(&mut self, prefix: &str) -> bool {
+        if self.rest().starts_with(prefix) {
+            self.position += prefix.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// true if the input continues with a section tag such as `D:`
+    fn at_section(&self) -> bool {
+        let rest = self.rest().as_bytes();
+        rest.len() >= 2 && rest[1] == b':' && matches!(rest[0], b'O' | b'G' | b'D' | b'S')
+    }
+
+    fn security_descriptor(&mut self) -> ParseResult<SecurityDescriptor> {
+        let mut sd = SecurityDescriptor::default();
+
+        while !self.is_empty() {
+            if self.eat("O:") {
+                if sd.owner.is_some() {
+                    return self.error("duplicate owner");
+                }
+                sd.owner = Some(self.trustee()?);
+            } else if self.eat("G:") {
+                if sd.group.is_some() {
+                    return self.error("duplicate group");
+                }
+                sd.group = Some(self.trustee()?);
+            } else if self.eat("D:") {
+                if sd.dacl.is_some() {
+                    return self.error("duplicate DACL");
+                }
+                sd.dacl = Some(self.acl()?);
+            } else if self.eat("S:") {
+                if sd.sacl.is_some() {
+                    return self.error("duplicate SACL");
+                }
+                sd.sacl = Some(self.acl()?);
+            } else {
+                return self.error("expected O:, G:, D: or S:");
+            }
+        }
+
+        Ok(sd)
+    }
+
+    fn trustee(&mut self) -> ParseResult<Trustee> {
+        if self.rest().starts_with("S-") {
+            Ok(Trustee::Sid(self.sid()?))
+        } else {
+            let alias = self.rest().get(..2).unwrap_or("");
+            if alias.len() == 2 && alias.bytes().all(|c|c.is_ascii_uppercase()) {
+                self.position += 2;
+                Ok(Trustee::Alias(alias.into()))
+            } else {
+                self.error("expected a SID or two letter alias")
+            }
+        }
+    }
+
+    /// an unsigned decimal, or a hexadecimal number prefixed with `0x`
+    fn number(&mut self) -> ParseResult<u64> {
+        let rest = self.rest();
+        let (digits, radix, prefix) = if rest.starts_with("0x") || rest.starts_with("0X") {
+            (&rest[2..], 16, 2)
+        } else {
+            (rest, 10, 0)
+        };
+        let len = digits.find(|c: char|!c.is_digit(radix)).unwrap_or(digits.len());
+        if len == 0 {
+            return self.error("expected a number");
+        }
+        match u64::from_str_radix(&digits[..len], radix) {
+            Ok(value) => {
+                self.position += prefix + len;
+                Ok(value)
+            },
+            Err(_) => self.error("number out of range"),
+        }
+    }
+
+    fn sid(&mut self) -> ParseResult<Sid> {
+        if !self.eat("S-") {
+            return self.error("expected S-");
+        }
+
+        let revision = self.number()?;
+        if revision > u8::MAX as u64 {
+            return self.error("SID revision out of range");
+        }
+        if !self.eat("-") {
+            return self.error("expected identifier authority");
+        }
+        let authority = self.number()?;
+        if authority > MAX_AUTHORITY {
+            return self.error("SID identifier authority out of range");
+        }
+
+        let mut sub_authorities = Vec::new();
+        while self.rest().starts_with('-') && self.rest()[1..].starts_with(|c: char|c.is_ascii_digit()) {
+            self.position += 1;
+            let sub_authority = self.number()?;
+            if sub_authority > u32::MAX as u64 {
+                return self.error("SID sub authority out of range");
+            }
+            if sub_authorities.len() == MAX_SUB_AUTHORITIES {
+                return self.error("too many SID sub authorities");
+            }
+            sub_authorities.push(sub_authority as u32);
+        }
+
+        Ok(Sid{ revision: revision as u8, authority, sub_authorities })
+    }
+
+    fn acl(&mut self) -> ParseResult<Acl> {
+        let mut acl = Acl::default();
+
+        loop {
+            if self.eat("NO_ACCESS_CONTROL") {
+                acl.flags.no_access_control = true;
+            } else if self.eat("AR") {
+                acl.flags.auto_inherit_req = true;
+            } else if self.eat("AI") {
+                acl.flags.auto_inherited = true;
+            } else if self.rest().starts_with('P') && !self.at_section() {
+                self.position += 1;
+                acl.flags.protected = true;
+            } else {
+                break;
+            }
+        }
+
+        while self.peek() == Some('(') {
+            acl.aces.push(self.ace()?);
+        }
+
+        if !self.is_empty() && !self.at_section() {
+            return self.error("expected an ACE or the next section");
+        }
+
+        Ok(acl)
+    }
+
+    /// split the body of an ACE on `;` outside of any nested parentheses or
+    /// quoted strings, which only occur in resource attributes
+    fn ace_fields(&mut self) -> ParseResult<Vec<(usize, &'a str)>> {
+        let start = self.position + 1;
+        let mut fields = Vec::new();
+        let mut field_start = start;
+        let mut depth = 0;
+        let mut quoted = false;
+
+        for (offset, c) in self.input[start..].char_indices() {
+            let index = start + offset;
+            match c {
+                '"' => quoted = !quoted,
+                _ if quoted => {},
+                '(' => depth += 1,
+                ')' if depth == 0 => {
+                    fields.push((field_start, &self.input[field_start..index]));
+                    self.position = index + 1;
+                    return Ok(fields);
+                },
+                ')' => depth -= 1,
+                ';' if depth == 0 => {
+                    fields.push((field_start, &self.input[field_start..index]));
+                    field_start = index + 1;
+                },
+                _ => {},
+            }
+        }
+
+        self.error("unterminated ACE")
+    }
+
+    fn ace(&mut self) -> ParseResult<Ace> {
+        let ace_start = self.position;
+        let fields = self.ace_fields()?;
+
+        if fields.len() != 6 && fields.len() != 7 {
+            return Err(ParseError{ position: ace_start, message: "expected 6 or 7 ACE fields" });
+        }
+
+        let field_error = |position: usize, message| ParseError{ position, message };
+
+        let (position, ace_type) = fields[0];
+        let ace_type = ACE_TYPES.iter()
+            .find(|(code,_)|*code == ace_type)
+            .map(|(_,ace_type)|*ace_type)
+            .ok_or_else(||field_error(position, "unknown ACE type"))?;
+
+        let (position, flags) = fields[1];
+        let flags = parse_codes(flags, ACE_FLAGS, |a,b|a|b)
+            .ok_or_else(||field_error(position, "unknown ACE flag"))?;
+
+        let (position, rights) = fields[2];
+        let rights = parse_rights(rights)
+            .ok_or_else(||field_error(position, "invalid access rights"))?;
+
+        let optional = |field: &str| if field.is_empty() { None } else { Some(String::from(field)) };
+
+        let (position, trustee) = fields[5];
+        let trustee = {
+            let mut parser = Parser::new(trustee);
+            match parser.trustee() {
+                Ok(trustee) if parser.is_empty() => trustee,
+                _ => return Err(field_error(position, "invalid trustee")),
+            }
+        };
+
+        Ok(Ace{
+            ace_type,
+            flags,
+            rights,
+            object_type: optional(fields[3].1),
+            inherit_object_type: optional(fields[4].1),
+            trustee,
+            resource_attribute: fields.get(6).map(|(_,field)|String::from(*field)),
+        })
+    }
+}
+
+/// parse a run of two letter codes, combining their values with `combine`
+fn parse_codes<T: Copy + Default>(input: &str, codes: &[(&str, T)], combine: impl Fn(T,T)->T) -> Option<T> {
+    let mut value = T::default();
+    let mut rest = input;
+    while !rest.is_empty() {
+        let (_, code_value) = codes.iter().find(|(code,_)|rest.starts_with(code))?;
+        value = combine(value, *code_value);
+        rest = &rest[2..];
+    }
+    Some(value)
+}
+
+fn parse_rights(input: &str) -> Option<u32> {
+    if input.starts_with("0x") || input.starts_with("0X") {
+        u32::from_str_radix(&input[2..], 16).ok()
+    } else if input.starts_with(|c: char|c.is_ascii_digit()) {
+        input.parse().ok()
+    } else {
+        parse_codes(input, RIGHTS, |a,b|a|b).or_else(||{
+            let all: Vec<(&str,u32)> = RIGHTS.iter().chain(COMPOSITE_RIGHTS).cloned().collect();
+            parse_codes(input, &all, |a,b|a|b)
+        })
+    }
+}
+
+impl FromStr for SecurityDescriptor {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> ParseResult<Self> {
+        Parser::new(input.trim()).security_descriptor()
+    }
+}
+
+impl FromStr for Sid {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> ParseResult<Self> {
+        let mut parser = Parser::new(input);
+        let sid = parser.sid()?;
+        if parser.is_empty() { Ok(sid) } else { parser.error("trailing characters after SID") }
+    }
+}
+
+impl FromStr for Trustee {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> ParseResult<Self> {
+        let mut parser = Parser::new(input);
+        let trustee = parser.trustee()?;
+        if parser.is_empty() { Ok(trustee) } else { parser.error("trailing characters after trustee") }
+    }
+}
+
+impl fmt::Display for SecurityDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(owner) = &self.owner {
+            write!(f, "O:{}", owner)?;
+        }
+        if let Some(group) = &self.group {
+            write!(f, "G:{}", group)?;
+        }
+        if let Some(dacl) = &self.dacl {
+            write!(f, "D:{}", dacl)?;
+        }
+        if let Some(sacl) = &self.sacl {
+            write!(f, "S:{}", sacl)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Acl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.flags.protected {
+            f.write_str("P")?;
+        }
+        if self.flags.auto_inherit_req {
+            f.write_str("AR")?;
+        }
+        if self.flags.auto_inherited {
+            f.write_str("AI")?;
+        }
+        if self.flags.no_access_control {
+            f.write_str("NO_ACCESS_CONTROL")?;
+        }
+        for ace in &self.aces {
+            write!(f, "{}", ace)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Ace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ace_type = ACE_TYPES.iter().find(|(_,ace_type)|*ace_type == self.ace_type).unwrap().0;
+
+        write!(f, "({};", ace_type)?;
+
+        for (code, flag) in ACE_FLAGS {
+            if self.flags.contains(*flag) {
+                f.write_str(code)?;
+            }
+        }
+
+        f.write_str(";")?;
+
+        let named = RIGHTS.iter().fold(0, |mask, (_, right)|mask | right);
+        if self.rights & !named == 0 {
+            for (code, right) in RIGHTS {
+                if self.rights & right != 0 {
+                    f.write_str(code)?;
+                }
+            }
+        } else {
+            write!(f, "0x{:x}", self.rights)?;
+        }
+
+        write!(f, ";{};{};{}",
+            self.object_type.as_deref().unwrap_or(""),
+            self.inherit_object_type.as_deref().unwrap_or(""),
+            self.trustee,
+        )?;
+
+        if let Some(resource_attribute) = &self.resource_attribute {
+            write!(f, ";{}", resource_attribute)?;
+        }
+
+        f.write_str(")")
+    }
+}
+
+impl fmt::Display for Trustee {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trustee::Alias(alias) => f.write_str(alias),
+            Trustee::Sid(sid) => sid.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for Sid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "S-{}-", self.revision)?;
+        if self.authority > u32::MAX as u64 {
+            write!(f, "0x{:012X}", self.authority)?;
+        } else {
+            write!(f, "{}", self.authority)?;
+        }
+        for sub_authority in &self.sub_authorities {
+            write!(f, "-{}", sub_authority)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// the default security descriptor windows gives a new service
+    const DEFAULT_SERVICE_SDDL: &str = "D:(A;;CCLCSWRPWPDTLOCRRC;;;SY)(A;;CCDCLCSWRPWPDTLOCRSDRCWDWO;;;BA)(A;;CCLCSWLOCRRC;;;IU)(A;;CCLCSWLOCRRC;;;SU)S:(AU;FA;CCDCLCSWRPWPDTLOCRSDRCWDWO;;;WD)";
+
+    fn round_trip(input: &str) {
+        let sd: SecurityDescriptor = input.parse().unwrap();
+        assert_eq!(sd.to_string(), input);
+    }
+
+    #[test]
+    fn default_service_descriptor() {
+        let sd: SecurityDescriptor = DEFAULT_SERVICE_SDDL.parse().unwrap();
+        let dacl = sd.dacl.as_ref().unwrap();
+        assert_eq!(dacl.aces.len(), 4);
+        assert_eq!(dacl.aces[0].ace_type, AceType::AccessAllowed);
+        assert_eq!(dacl.aces[0].rights, 0x2_01FD);
+        assert_eq!(dacl.aces[0].trustee, Trustee::Alias("SY".into()));
+        assert_eq!(sd.sacl.as_ref().unwrap().aces[0].flags, AceFlags::FAILED_ACCESS);
+        round_trip(DEFAULT_SERVICE_SDDL);
+    }
+
+    #[test]
+    fn owner_group_and_flags() {
+        let sd: SecurityDescriptor = "O:BAG:SYD:PAI(A;OICIIO;GA;;;CO)".parse().unwrap();
+        assert_eq!(sd.owner, Some(Trustee::Alias("BA".into())));
+        assert_eq!(sd.group, Some(Trustee::Alias("SY".into())));
+        let dacl = sd.dacl.as_ref().unwrap();
+        assert!(dacl.flags.protected && dacl.flags.auto_inherited);
+        assert_eq!(dacl.aces[0].flags, AceFlags::OBJECT_INHERIT | AceFlags::CONTAINER_INHERIT | AceFlags::INHERIT_ONLY);
+        round_trip("O:BAG:SYD:PAI(A;OICIIO;GA;;;CO)");
+    }
+
+    #[test]
+    fn literal_sids() {
+        let sid: Sid = "S-1-5-21-1004336348-1177238915-682003330-512".parse().unwrap();
+        assert_eq!(sid.authority, 5);
+        assert_eq!(sid.sub_authorities, vec![21, 1004336348, 1177238915, 682003330, 512]);
+        round_trip("O:S-1-5-32-544G:S-1-5-18D:(A;;LCRPWP;;;S-1-5-21-1-2-3-1001)");
+        assert_eq!("S-1-0x123456789ABC-1".parse::<Sid>().unwrap().to_string(), "S-1-0x123456789ABC-1");
+    }
+
+    #[test]
+    fn sid_followed_by_section() {
+        let sd: SecurityDescriptor = "O:S-1-5-18D:(A;;GA;;;SY)".parse().unwrap();
+        assert_eq!(sd.owner, Some(Trustee::Sid(Sid{ revision: 1, authority: 5, sub_authorities: vec![18] })));
+        assert!(sd.dacl.is_some());
+    }
+
+    #[test]
+    fn hex_and_composite_rights() {
+        let sd: SecurityDescriptor = "D:(A;;0x1200a9;;;WD)(A;;FA;;;BA)".parse().unwrap();
+        let aces = &sd.dacl.as_ref().unwrap().aces;
+        assert_eq!(aces[0].rights, 0x1200a9);
+        assert_eq!(aces[1].rights, 0x1F01FF);
+        assert_eq!(sd.to_string(), "D:(A;;0x1200a9;;;WD)(A;;0x1f01ff;;;BA)");
+    }
+
+    #[test]
+    fn object_aces_and_resource_attributes() {
+        round_trip("D:(OA;CI;RP;4c164200-20c0-11d0-a768-00aa006e0529;bf967aba-0de6-11d0-a285-00aa003049e2;RU)");
+        round_trip("S:(RA;;;;;WD;(\"Project\",TS,0x0,\"a;b)\"))");
+    }
+
+    #[test]
+    fn null_and_empty_acls() {
+        let sd: SecurityDescriptor = "D:NO_ACCESS_CONTROL".parse().unwrap();
+        assert!(sd.dacl.as_ref().unwrap().flags.no_access_control);
+        round_trip("D:NO_ACCESS_CONTROL");
+        let sd: SecurityDescriptor = "D:S:".parse().unwrap();
+        assert_eq!(sd.dacl, Some(Acl::default()));
+        assert_eq!(sd.sacl, Some(Acl::default()));
+    }
+
+    #[test]
+    fn builds_operator_ace() {
+        let mut sd: SecurityDescriptor = "D:(A;;CCLCSWRPWPDTLOCRRC;;;SY)".parse().unwrap();
+        let operators: Trustee = "S-1-5-21-1-2-3-1108".parse().unwrap();
+        sd.dacl.as_mut().unwrap().aces.push(Ace::allow(operators, 0x0000_0034));
+        assert_eq!(sd.to_string(), "D:(A;;CCLCSWRPWPDTLOCRRC;;;SY)(A;;LCRPWP;;;S-1-5-21-1-2-3-1108)");
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let error = |input: &str| input.parse::<SecurityDescriptor>().unwrap_err();
+        assert_eq!(error("X:BA").position, 0);
+        assert_eq!(error("O:B").message, "expected a SID or two letter alias");
+        assert_eq!(error("D:(A;;GA;;;SY").message, "unterminated ACE");
+        assert_eq!(error("D:(A;;GA;;SY)").message, "expected 6 or 7 ACE fields");
+        assert_eq!(error("D:(Q;;GA;;;SY)").message, "unknown ACE type");
+        assert_eq!(error("D:(A;XX;GA;;;SY)").message, "unknown ACE flag");
+        assert_eq!(error("D:(A;;ZZ;;;SY)").message, "invalid access rights");
+        assert_eq!(error("D:(A;;GA;;;S-1-x)").message, "invalid trustee");
+        assert_eq!(error("O:BAO:SY").message, "duplicate owner");
+        assert_eq!(error("O:S-1-5-99999999999").message, "SID sub authority out of range");
+    }
+}
@@ -1,13 +1,11 @@
 
-use winapi::shared::winerror::ERROR_INVALID_DATA;
-
 use serde::{self, de, de::IntoDeserializer};
 
-use std::{ffi::OsString, vec::IntoIter,convert::TryInto};
+use std::{vec::IntoIter,convert::TryInto};
 
 use crate::{
     Result, Error, registry::{
-        Key, KeyExt, ValueBuf, KeyNameIterator, ValueNameIterator
+        KeyTree, ValueBuf, ERROR_INVALID_DATA
     }
 };
 
@@ -17,19 +15,21 @@ impl de::Error for Error {
     }
 }
 
-pub struct Deserializer {
-    keys: Vec<Key>,
+/// reads a value stored under `name` from any `KeyTree`, such as a `Key`, a
+/// `MemoryKey` or a `HiveKey`
+pub struct Deserializer<K> {
+    keys: Vec<K>,
     name: Option<String>
 }
 
-impl Deserializer {
+impl<K: KeyTree> Deserializer<K> {
 
-    pub fn new(key: Key, name: String) -> Self {
+    pub fn new(key: K, name: String) -> Self {
         Self{ keys: vec![ key ], name: Some(name) }
     }
 
     pub fn push(&mut self, name: String) -> Result<()> {
-        eprintln!("pushing: {}", name);
+        tracing::trace!("push: {:?}", name);
         self.open()?;
         self.name = Some(name);
         Ok(())
@@ -37,74 +37,56 @@ impl Deserializer {
 
     pub fn pop(&mut self) {
         if self.name.take().is_none() {
-            eprintln!("closing key");
+            tracing::trace!("pop key");
             self.keys.pop().unwrap();
         } else {
-            eprintln!("poping name");
+            tracing::trace!("pop name");
         }
     }
 
     pub fn query_value(&mut self) -> Result<ValueBuf> {
         let name = self.name.take().unwrap();
-        eprintln!("query-popping: {}", name);
-        self.keys.last().unwrap().query_value(name)
+        tracing::trace!("query value: {:?}", name);
+        self.keys.last().unwrap().get_value(&name)
     }
 
-    pub fn iter_keys(&mut self) -> Result<KeyNameIterator> {
+    pub fn iter_names(&mut self) -> Result<Vec<String>> {
         self.open()?;
-        Ok(self.keys.last().unwrap().iter_key_names())
-    }
-
-    pub fn iter_values(&mut self) -> Result<ValueNameIterator> {
-        self.open()?;
-        Ok(self.keys.last().unwrap().iter_value_names())
-    }
-
-    pub fn iter_names(&mut self) -> Result<Vec<OsString>> {
-        let mut names = Vec::with_capacity(64);
-        for name in self.iter_keys()? {
-            names.push(name?);
-        }
-        for name in self.iter_values()? {
-            names.push(name?);
-        }
+        let key = self.keys.last().unwrap();
+        let mut names = key.subkey_names()?;
+        names.extend(key.value_names()?);
         Ok(names)
     }
 
     fn open(&mut self) -> Result<()> {
         if let Some(name) = self.name.take() {
-            eprintln!("open key: {}", name);
-            let subkey = self.keys.last().unwrap().open(name)?;
+            tracing::trace!("open: {:?}", name);
+            let subkey = self.keys.last().unwrap().open_subkey(&name)?;
             self.keys.push(subkey);
         }
         Ok(())
     }
 }
 
-struct StructMapAccess<'a>{
-    des: &'a mut Deserializer,
+struct StructMapAccess<'a,K>{
+    des: &'a mut Deserializer<K>,
     iter: IntoIter<String>,
 }
 
-impl<'a> StructMapAccess<'a> {
-    fn new(des: &'a mut Deserializer, _fields: &'static [&'static str]) -> Result<Self> {
-        let raw_names = des.iter_names()?;
-        let mut names = Vec::with_capacity(raw_names.len());
-        for raw_name in raw_names {
-            names.push(raw_name.into_string().map_err(|_|Error{code:ERROR_INVALID_DATA})?);
-        }
-        let iter = names.into_iter();
+impl<'a,K: KeyTree> StructMapAccess<'a,K> {
+    fn new(des: &'a mut Deserializer<K>, _fields: &'static [&'static str]) -> Result<Self> {
+        let iter = des.iter_names()?.into_iter();
         // todo - verify names match fields
         Ok(Self{des,iter})
     }
 }
 
-impl<'de,'a> de::MapAccess<'de> for StructMapAccess<'a> {
+impl<'de,'a,T: KeyTree> de::MapAccess<'de> for StructMapAccess<'a,T> {
     type Error = Error;
 
     fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>> where K: de::DeserializeSeed<'de> {
         if let Some(key) = self.iter.next() {
-            let value = { let de : &str = &key; seed.deserialize(de.into_deserializer()).map(|v|Some(v))? };
+            let value = { let de : &str = &key; seed.deserialize(de.into_deserializer()).map(Some)? };
             self.des.push(key)?;
             Ok(value)
         } else {
@@ -117,7 +99,7 @@ impl<'de,'a> de::MapAccess<'de> for StructMapAccess<'a> {
     }
 }
 
-impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer {
+impl<'de, K: KeyTree> de::Deserializer<'de> for &mut Deserializer<K> {
     type Error = Error;
 
     fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
@@ -188,8 +170,9 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer {
         panic!()
     }
 
-    fn deserialize_option<V>(self, _visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
-        panic!()
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
+        // only names that exist are visited, a missing field is `None`
+        visitor.visit_some(self)
     }
 
     fn deserialize_unit<V>(self, _visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
@@ -5,7 +5,7 @@ mod deserializer;
 pub use serializer::*;
 pub use deserializer::*;
 
-#[cfg(test)]
+#[cfg(all(test, windows))]
 mod test {
     use super::*;
     use crate::registry::HKEY_CURRENT_USER;
@@ -44,6 +44,12 @@ mod test {
         }
     }
 
+    #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
+    struct Optional {
+        value_1: Option<String>,
+        value_2: Option<u32>,
+    }
+
     fn ser(name: &str, value: &impl Serialize) {
 
         let mut serializer = Serializer::new(HKEY_CURRENT_USER.create("SOFTWARE\\n8ware\\test\\winsvc").unwrap(), name.into());
@@ -74,6 +80,13 @@ mod test {
     #[test]
     fn simple_nested() { check_ser_de("simple_nested", SimpleNested::default()); }
 
+    #[test]
+    fn optional() {
+        ser("optional", &Optional{ value_1: Some("Hello World".into()), value_2: Some(42) });
+        ser("optional", &Optional{ value_1: None, value_2: Some(7) });
+        assert_eq!(de::<Optional>("optional"), Optional{ value_1: None, value_2: Some(7) });
+    }
+
     #[test]
     fn sequence() {
         let mut v = Vec::new();
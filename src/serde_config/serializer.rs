@@ -1,25 +1,26 @@
-
 use serde::ser;
 
-use crate::{Error,Result,registry::{Key,KeyExt,ValueBuf}};
+use crate::{Error,Result,registry::{KeyTree,ValueBuf,ERROR_FILE_NOT_FOUND,ERROR_INVALID_DATA}};
 
-pub struct Serializer{
-    keys: Vec<Key>,
+/// writes a value under `name` to any `KeyTree`, such as a `Key` or the copy
+/// a `MemoryKey` stages
+pub struct Serializer<K>{
+    keys: Vec<K>,
     name: Option<String>,
 }
 
 pub struct PanicSerializer;
-pub struct SeqSerializer<'a>(&'a mut Serializer,u32);
+pub struct SeqSerializer<'a,K>(&'a mut Serializer<K>,u32);
 
-impl Serializer {
-    pub fn new(parent_key: Key, value_name: String) -> Self {
+impl<K: KeyTree> Serializer<K> {
+    pub fn new(parent_key: K, value_name: String) -> Self {
         Self{keys: vec![ parent_key ], name: Some(value_name)}
     }
 
     fn push(&mut self, name: String) -> Result<()> {
-        if let Some(parent_name) = std::mem::replace(&mut self.name, None) {
+        if let Some(parent_name) = self.name.take() {
             tracing::trace!("open: {:?}", parent_name);
-            let sub_key = self.keys.last().unwrap().create(parent_name)?;
+            let sub_key = self.keys.last().unwrap().create_subkey(&parent_name)?;
             self.keys.push(sub_key);
         }
         tracing::trace!("push: {:?}", name);
@@ -28,15 +29,26 @@ impl Serializer {
     }
 
     fn write(&mut self, value: impl Into<ValueBuf>) -> Result<()> {
-        if let Some(name) = std::mem::replace(&mut self.name, None) {
+        if let Some(name) = self.name.take() {
             let value = value.into();
             tracing::trace!("set value: {:?}={:?}", name, value);
-            self.keys.last().unwrap().set_value(name, &value)
+            self.keys.last().unwrap().put_value(&name, &value)
         } else {
             panic!()
         }
     }
 
+    /// remove whatever an earlier save stored under the current name
+    fn remove(&mut self) -> Result<()> {
+        if let Some(name) = &self.name {
+            tracing::trace!("remove: {:?}", name);
+            let key = self.keys.last().unwrap();
+            ignore_not_found(key.remove_value(name))?;
+            ignore_not_found(key.remove_subtree(name))?;
+        }
+        Ok(())
+    }
+
     fn pop(&mut self) {
         if self.name.is_some() {
             tracing::trace!("pop name: {:?}", self.name.as_ref().unwrap());
@@ -48,13 +60,20 @@ impl Serializer {
     }
 }
 
+fn ignore_not_found(result: Result<()>) -> Result<()> {
+    match result {
+        Err(Error{code: ERROR_FILE_NOT_FOUND}) => Ok(()),
+        result => result,
+    }
+}
+
 impl ser::Error for Error {
    fn custom<T>(_err: T) -> Self {
-       todo!()
+       Error{code:ERROR_INVALID_DATA}
    }
 }
 
-impl<'a> ser::Serializer for &'a mut Serializer {
+impl<'a, K: KeyTree> ser::Serializer for &'a mut Serializer<K> {
 
     // error handling
 
@@ -63,9 +82,9 @@ impl<'a> ser::Serializer for &'a mut Serializer {
 
     // subtype serializers
 
-    type SerializeSeq = SeqSerializer<'a>;
-    type SerializeTuple = SeqSerializer<'a>;
-    type SerializeTupleStruct = SeqSerializer<'a>;
+    type SerializeSeq = SeqSerializer<'a,K>;
+    type SerializeTuple = SeqSerializer<'a,K>;
+    type SerializeTupleStruct = SeqSerializer<'a,K>;
     type SerializeTupleVariant = PanicSerializer;
     type SerializeMap = PanicSerializer;
     type SerializeStruct = Self;
@@ -90,7 +109,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     fn serialize_str(self, v: &str) -> Result<()> { self.write(v) }
     fn serialize_bytes(self, v: &[u8]) -> Result<()> { self.write(v) }
 
-    fn serialize_none(self) -> Result<()> { Ok(()) }
+    fn serialize_none(self) -> Result<()> { self.remove() }
     fn serialize_some<T: ?Sized + ser::Serialize> (self, value: &T) -> Result<()> { value.serialize(self) }
 
     fn serialize_unit(self) -> Result<()> { panic!() }
@@ -179,7 +198,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeSeq for SeqSerializer<'a> {
+impl<'a, K: KeyTree> ser::SerializeSeq for SeqSerializer<'a,K> {
 
     type Ok = ();
     type Error = Error;
@@ -200,7 +219,7 @@ impl<'a> ser::SerializeSeq for SeqSerializer<'a> {
     }
 }
 
-impl<'a> ser::SerializeTuple for SeqSerializer<'a> {
+impl<'a, K: KeyTree> ser::SerializeTuple for SeqSerializer<'a,K> {
     type Ok = ();
     type Error = Error;
 
@@ -220,7 +239,7 @@ impl<'a> ser::SerializeTuple for SeqSerializer<'a> {
 }
 
 
-impl<'a> ser::SerializeTupleStruct for SeqSerializer<'a> {
+impl<'a, K: KeyTree> ser::SerializeTupleStruct for SeqSerializer<'a,K> {
     type Ok = ();
     type Error = Error;
 
@@ -265,7 +284,7 @@ impl ser::SerializeTupleVariant for PanicSerializer {
 }
 
 
-impl<'a> ser::SerializeMap for PanicSerializer {
+impl ser::SerializeMap for PanicSerializer {
     type Ok = ();
     type Error = Error;
 
@@ -302,7 +321,7 @@ impl<'a> ser::SerializeMap for PanicSerializer {
     }
 }
 
-impl<'a> ser::SerializeStruct for &'a mut Serializer {
+impl<K: KeyTree> ser::SerializeStruct for &mut Serializer<K> {
     type Ok = ();
     type Error = Error;
 
@@ -321,7 +340,7 @@ impl<'a> ser::SerializeStruct for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeStructVariant for PanicSerializer {
+impl ser::SerializeStructVariant for PanicSerializer {
     type Ok = ();
     type Error = Error;
 
@@ -1,12 +1,18 @@
 
 use std::{
+    fmt,
+    ffi::OsString,
+    os::windows::ffi::{OsStrExt,OsStringExt},
     ptr::null_mut,
     mem::MaybeUninit,
+    time::{Duration,Instant},
+    sync::{Arc,atomic::{AtomicBool,Ordering::Relaxed}},
 };
 
 use winapi::{
     shared::winerror::{
-        ERROR_GEN_FAILURE,
+        ERROR_INVALID_DATA,
+        ERROR_INVALID_PARAMETER,
     },
     um::{
         synchapi::SleepEx,
@@ -16,11 +22,27 @@ use winapi::{
             DeleteService,
             ControlService,
             CreateServiceW,
+            ChangeServiceConfigW,
             OpenSCManagerW,
             CloseServiceHandle,
             QueryServiceStatusEx,
             SC_HANDLE,
+            SC_MANAGER_CONNECT,
+            SC_MANAGER_CREATE_SERVICE,
+            SC_MANAGER_ENUMERATE_SERVICE,
+            SC_MANAGER_LOCK,
+            SC_MANAGER_QUERY_LOCK_STATUS,
+            SC_MANAGER_MODIFY_BOOT_CONFIG,
             SC_MANAGER_ALL_ACCESS,
+            SERVICE_QUERY_CONFIG,
+            SERVICE_CHANGE_CONFIG,
+            SERVICE_QUERY_STATUS,
+            SERVICE_ENUMERATE_DEPENDENTS,
+            SERVICE_START,
+            SERVICE_STOP,
+            SERVICE_PAUSE_CONTINUE,
+            SERVICE_INTERROGATE,
+            SERVICE_USER_DEFINED_CONTROL,
             SERVICE_ALL_ACCESS,
             SERVICE_STATUS,
             SERVICE_STATUS_PROCESS,
@@ -29,11 +51,37 @@ use winapi::{
             SERVICE_START_PENDING,
             SERVICE_STOPPED,
             SERVICE_STOP_PENDING,
+            SERVICE_PAUSED,
+            SERVICE_PAUSE_PENDING,
+            SERVICE_CONTINUE_PENDING,
             SERVICE_CONTROL_STOP,
+            SERVICE_CONTROL_PAUSE,
+            SERVICE_CONTROL_CONTINUE,
+            SERVICE_CONTROL_INTERROGATE,
+            SERVICE_NO_CHANGE,
+            SERVICE_RUNS_IN_SYSTEM_PROCESS,
+            SERVICE_ACCEPT_STOP,
+            SERVICE_ACCEPT_PAUSE_CONTINUE,
+            SERVICE_ACCEPT_SHUTDOWN,
+            SERVICE_ACCEPT_PARAMCHANGE,
+            SERVICE_ACCEPT_NETBINDCHANGE,
+            SERVICE_ACCEPT_HARDWAREPROFILECHANGE,
+            SERVICE_ACCEPT_POWEREVENT,
+            SERVICE_ACCEPT_SESSIONCHANGE,
+            SERVICE_ACCEPT_PRESHUTDOWN,
+            SERVICE_ACCEPT_TIMECHANGE,
+            SERVICE_ACCEPT_TRIGGEREVENT,
         },
         winnt::{
+            ACCESS_SYSTEM_SECURITY,
+            DELETE,
+            READ_CONTROL,
+            WRITE_DAC,
+            WRITE_OWNER,
             SERVICE_WIN32_OWN_PROCESS,
             SERVICE_AUTO_START,
+            SERVICE_DEMAND_START,
+            SERVICE_DISABLED,
             SERVICE_ERROR_NORMAL,
         },
     },
@@ -41,29 +89,100 @@ use winapi::{
 
 use super::{
     to_wstr,
+    command_line,
+    account::Account,
+    service_name::{ServiceName,DisplayName},
     Error, Result,
     get_this_module_filename_raw,
 };
 
-#[derive(Copy,Clone)]
-pub enum Access {
-    All,
+bit_flags!{
+    /// rights requested when opening the service control manager
+    Access {
+        /// connect to the service control manager
+        CONNECT = SC_MANAGER_CONNECT,
+        /// create services, required by `create_self_service_simple`
+        CREATE_SERVICE = SC_MANAGER_CREATE_SERVICE,
+        /// enumerate services, required by `watch_services`
+        ENUMERATE_SERVICE = SC_MANAGER_ENUMERATE_SERVICE,
+        /// lock the service database
+        LOCK = SC_MANAGER_LOCK,
+        /// query the lock status of the service database
+        QUERY_LOCK_STATUS = SC_MANAGER_QUERY_LOCK_STATUS,
+        /// change the boot configuration
+        MODIFY_BOOT_CONFIG = SC_MANAGER_MODIFY_BOOT_CONFIG,
+        /// every right, which requires administrator privileges
+        ALL = SC_MANAGER_ALL_ACCESS,
+    }
 }
 
-impl Access {
-    fn into_raw(self) -> u32 {
-        match self {
-            Access::All => SC_MANAGER_ALL_ACCESS
-        }
+bit_flags!{
+    /// rights requested when opening a service
+    ServiceAccess {
+        /// query the status, required by every method that waits for a state
+        QUERY_STATUS = SERVICE_QUERY_STATUS,
+        /// query the configuration
+        QUERY_CONFIG = SERVICE_QUERY_CONFIG,
+        /// change the configuration
+        CHANGE_CONFIG = SERVICE_CHANGE_CONFIG,
+        /// enumerate the services depending on this one
+        ENUMERATE_DEPENDENTS = SERVICE_ENUMERATE_DEPENDENTS,
+        /// required by `start`
+        START = SERVICE_START,
+        /// required by `stop`
+        STOP = SERVICE_STOP,
+        /// required by `pause` and `resume`
+        PAUSE_CONTINUE = SERVICE_PAUSE_CONTINUE,
+        /// required by `interrogate`
+        INTERROGATE = SERVICE_INTERROGATE,
+        /// required by `send_control`
+        USER_DEFINED_CONTROL = SERVICE_USER_DEFINED_CONTROL,
+        /// required by `delete`
+        DELETE = DELETE,
+        /// read the security descriptor
+        READ_CONTROL = READ_CONTROL,
+        /// change the discretionary access control list
+        WRITE_DAC = WRITE_DAC,
+        /// change the owner
+        WRITE_OWNER = WRITE_OWNER,
+        /// read or change the system access control list
+        ACCESS_SYSTEM_SECURITY = ACCESS_SYSTEM_SECURITY,
+        /// every right, which usually requires administrator privileges
+        ALL = SERVICE_ALL_ACCESS,
+    }
+}
+
+bit_flags!{
+    /// controls a service currently accepts
+    AcceptedControls {
+        STOP = SERVICE_ACCEPT_STOP,
+        PAUSE_CONTINUE = SERVICE_ACCEPT_PAUSE_CONTINUE,
+        SHUTDOWN = SERVICE_ACCEPT_SHUTDOWN,
+        PARAM_CHANGE = SERVICE_ACCEPT_PARAMCHANGE,
+        NETBIND_CHANGE = SERVICE_ACCEPT_NETBINDCHANGE,
+        HARDWARE_PROFILE_CHANGE = SERVICE_ACCEPT_HARDWAREPROFILECHANGE,
+        POWER_EVENT = SERVICE_ACCEPT_POWEREVENT,
+        SESSION_CHANGE = SERVICE_ACCEPT_SESSIONCHANGE,
+        PRESHUTDOWN = SERVICE_ACCEPT_PRESHUTDOWN,
+        TIME_CHANGE = SERVICE_ACCEPT_TIMECHANGE,
+        TRIGGER_EVENT = SERVICE_ACCEPT_TRIGGEREVENT,
     }
 }
 
 pub const LOCAL_SERVICE: &'static str = "NT AUTHORITY\\LocalService";
 pub const NETWORK_SERVICE: &'static str = "NT AUTHORITY\\NetworkService";
 
-pub struct Service(SC_HANDLE);
+pub struct Service(pub(crate) SC_HANDLE, pub(crate) Origin);
+
+pub struct ServiceControlManager(pub(crate) SC_HANDLE, pub(crate) Origin);
 
-pub struct ServiceControlManager(SC_HANDLE);
+/// the machine and service a handle was opened for, so a notification worker
+/// can open a handle of its own
+#[derive(Clone,Debug,Default)]
+pub(crate) struct Origin {
+    pub(crate) machine: Option<String>,
+    pub(crate) service: Option<String>,
+}
 
 trait StatusStruct{
     const INFO_LEVEL: u32;
@@ -73,7 +192,247 @@ impl StatusStruct for SERVICE_STATUS_PROCESS {
     const INFO_LEVEL: u32 = SC_STATUS_PROCESS_INFO;
 }
 
+/// the current state of a service as reported by the service control manager
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub enum ServiceState {
+    Stopped,
+    StartPending,
+    StopPending,
+    Running,
+    ContinuePending,
+    PausePending,
+    Paused,
+}
+
+impl ServiceState {
+    pub(crate) fn from_raw(state: u32) -> Result<Self> {
+        match state {
+            SERVICE_STOPPED => Ok(Self::Stopped),
+            SERVICE_START_PENDING => Ok(Self::StartPending),
+            SERVICE_STOP_PENDING => Ok(Self::StopPending),
+            SERVICE_RUNNING => Ok(Self::Running),
+            SERVICE_CONTINUE_PENDING => Ok(Self::ContinuePending),
+            SERVICE_PAUSE_PENDING => Ok(Self::PausePending),
+            SERVICE_PAUSED => Ok(Self::Paused),
+            _ => Err(Error{code:ERROR_INVALID_DATA}),
+        }
+    }
+}
+
+/// the status of a service and the process hosting it
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub struct Status {
+    pub state: ServiceState,
+    pub controls_accepted: AcceptedControls,
+    /// `ERROR_SERVICE_SPECIFIC_ERROR` when `service_specific_exit_code` is set
+    pub win32_exit_code: u32,
+    pub service_specific_exit_code: u32,
+    pub check_point: u32,
+    pub wait_hint: Duration,
+    /// the hosting process, `None` while the service is stopped
+    pub process_id: Option<u32>,
+    /// hosted by a system process that must stay running
+    pub runs_in_system_process: bool,
+}
+
+impl Status {
+    fn from_raw(status: &SERVICE_STATUS_PROCESS) -> Result<Self> {
+        Ok(Self{
+            state: ServiceState::from_raw(status.dwCurrentState)?,
+            controls_accepted: AcceptedControls::from_raw(status.dwControlsAccepted),
+            win32_exit_code: status.dwWin32ExitCode,
+            service_specific_exit_code: status.dwServiceSpecificExitCode,
+            check_point: status.dwCheckPoint,
+            wait_hint: Duration::from_millis(status.dwWaitHint as u64),
+            process_id: if status.dwProcessId != 0 { Some(status.dwProcessId) } else { None },
+            runs_in_system_process: status.dwServiceFlags & SERVICE_RUNS_IN_SYSTEM_PROCESS != 0,
+        })
+    }
+}
+
+/// when the service control manager starts a service
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub enum StartType {
+    /// at boot
+    Auto,
+    /// only when requested, or by one of its triggers
+    Demand,
+    Disabled,
+}
+
+impl StartType {
+    pub(crate) fn into_raw(self) -> u32 {
+        match self {
+            Self::Auto => SERVICE_AUTO_START,
+            Self::Demand => SERVICE_DEMAND_START,
+            Self::Disabled => SERVICE_DISABLED,
+        }
+    }
+}
+
+/// changes applied to an installed service by `Service::update`, fields
+/// left as `None` keep their current value
+#[derive(Clone,Debug,Default)]
+pub struct ServiceSpec {
+    /// executable to launch, this one if only `arguments` are given
+    pub program: Option<OsString>,
+    /// arguments passed to the executable, none if only `program` is given
+    pub arguments: Option<Vec<String>>,
+    pub account: Option<Account>,
+    pub start_type: Option<StartType>,
+    pub display_name: Option<DisplayName>,
+}
+
+impl ServiceSpec {
+    pub fn new() -> Self { Default::default() }
+
+    pub fn program(mut self, program: impl Into<OsString>) -> Self {
+        self.program = Some(program.into());
+        self
+    }
+
+    pub fn arguments<A: AsRef<str>>(mut self, arguments: &[A]) -> Self {
+        self.arguments = Some(arguments.iter().map(|argument|argument.as_ref().into()).collect());
+        self
+    }
+
+    pub fn account(mut self, account: Account) -> Self {
+        self.account = Some(account);
+        self
+    }
+
+    pub fn start_type(mut self, start_type: StartType) -> Self {
+        self.start_type = Some(start_type);
+        self
+    }
+
+    pub fn display_name(mut self, display_name: DisplayName) -> Self {
+        self.display_name = Some(display_name);
+        self
+    }
+
+    /// the command line to launch, `None` to keep the current one
+    fn binary_path_name(&self) -> Result<Option<Vec<u16>>> {
+        if self.program.is_none() && self.arguments.is_none() {
+            return Ok(None);
+        }
+
+        let program = match &self.program {
+            Some(program) => program.encode_wide().collect(),
+            None => get_this_module_filename_raw()?,
+        };
+
+        let arguments: Vec<Vec<u16>> = self.arguments.iter()
+            .flatten()
+            .map(|argument|argument.encode_utf16().collect())
+            .collect();
+
+        let mut binary_path_name = command_line::join_wide(&program, &arguments)
+            .map_err(|_|Error{code:ERROR_INVALID_PARAMETER})?;
+        binary_path_name.push(0);
+
+        Ok(Some(binary_path_name))
+    }
+}
+
+/// shared flag used to abandon a wait from another thread
+#[derive(Clone,Debug,Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self { Default::default() }
+
+    pub fn cancel(&self) { self.0.store(true, Relaxed) }
+
+    pub fn is_cancelled(&self) -> bool { self.0.load(Relaxed) }
+}
+
+/// controls how long `Service` operations wait for a state transition
+#[derive(Clone,Debug,Default)]
+pub struct WaitOptions {
+    /// give up once this much time has passed, regardless of progress
+    pub timeout: Option<Duration>,
+    /// give up as soon as this token is cancelled
+    pub cancel: Option<CancelToken>,
+}
+
+impl WaitOptions {
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
+    }
+}
+
+/// reasons a wait for a service state transition can fail
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub enum WaitError {
+    /// a system call failed
+    System(Error),
+    /// the overall timeout expired while the service was in `state`
+    Timeout{state: ServiceState},
+    /// the service stopped advancing its check point within its wait hint
+    Stalled{state: ServiceState, check_point: u32, wait_hint: Duration},
+    /// the service moved to a state other than the one being waited for
+    Unexpected{state: ServiceState},
+    /// the wait was cancelled while the service was in `state`
+    Cancelled{state: ServiceState},
+}
+
+pub type WaitResult<T> = std::result::Result<T,WaitError>;
+
+impl From<Error> for WaitError {
+    fn from(error: Error) -> Self { Self::System(error) }
+}
+
+impl fmt::Display for WaitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WaitError::System(error) => error.fmt(f),
+            WaitError::Timeout{state} =>
+                write!(f, "timed out waiting for service, stuck in {:?}", state),
+            WaitError::Stalled{state, check_point, wait_hint} =>
+                write!(f, "service stuck in {:?}, check point {} did not advance within {:?}", state, check_point, wait_hint),
+            WaitError::Unexpected{state} =>
+                write!(f, "service unexpectedly entered {:?}", state),
+            WaitError::Cancelled{state} =>
+                write!(f, "cancelled waiting for service in {:?}", state),
+        }
+    }
+}
+
+impl std::error::Error for WaitError {}
+
+/// bounds on the interval between status polls, the wait hint is divided by
+/// ten and clamped to this range as recommended by the SCM documentation
+const MIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// granularity used to notice cancellation while sleeping
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+fn poll_interval(wait_hint: Duration) -> Duration {
+    (wait_hint / 10).max(MIN_POLL_INTERVAL).min(MAX_POLL_INTERVAL)
+}
+
+/// least time allowed without check point progress, many services report a
+/// wait hint of 0
+const MIN_STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn stall_timeout(wait_hint: Duration) -> Duration {
+    wait_hint.max(MIN_STALL_TIMEOUT)
+}
+
 impl Service {
+    /// mark the service for deletion, requires `ServiceAccess::DELETE`
     pub fn delete(&self) -> Result<()> {
         if unsafe { DeleteService(self.0) } != 0 {
             Ok(())
@@ -82,11 +441,17 @@ impl Service {
         }
     }
 
-    pub fn start(&self) -> Result<()> {
-        if !self.wait_for_start()? {
+    /// start the service and wait for it to report that it is running,
+    /// requires `ServiceAccess::START` and `ServiceAccess::QUERY_STATUS`
+    pub fn start(&self) -> WaitResult<()> {
+        self.start_with(&WaitOptions::default())
+    }
+
+    pub fn start_with(&self, options: &WaitOptions) -> WaitResult<()> {
+        if !self.wait_for_start(options)? {
             self.raw_service_start()?;
-            if !self.wait_for_start()? {
-                Err(Error{code:ERROR_GEN_FAILURE})
+            if !self.wait_for_start(options)? {
+                Err(WaitError::Unexpected{state: self.query_progress()?.0})
             } else {
                 Ok(())
             }
@@ -95,11 +460,111 @@ impl Service {
         }
     }
 
-    pub fn stop(&self) -> Result<()> {
-        if !self.wait_for_stop()? {
-            self.raw_control_service(SERVICE_CONTROL_STOP)?;
-            if !self.wait_for_stop()? {
-                Err(Error{code:ERROR_GEN_FAILURE})
+    /// stop the service and wait for it to report that it is stopped,
+    /// requires `ServiceAccess::STOP` and `ServiceAccess::QUERY_STATUS`
+    pub fn stop(&self) -> WaitResult<()> {
+        self.stop_with(&WaitOptions::default())
+    }
+
+    pub fn stop_with(&self, options: &WaitOptions) -> WaitResult<()> {
+        self.control_and_wait(SERVICE_CONTROL_STOP, ServiceState::StopPending, ServiceState::Stopped, options)
+    }
+
+    /// pause a running service and wait for it to report that it is paused,
+    /// requires `ServiceAccess::PAUSE_CONTINUE` and `ServiceAccess::QUERY_STATUS`
+    pub fn pause(&self) -> WaitResult<()> {
+        self.pause_with(&WaitOptions::default())
+    }
+
+    pub fn pause_with(&self, options: &WaitOptions) -> WaitResult<()> {
+        self.control_and_wait(SERVICE_CONTROL_PAUSE, ServiceState::PausePending, ServiceState::Paused, options)
+    }
+
+    /// continue a paused service and wait for it to report that it is running,
+    /// requires `ServiceAccess::PAUSE_CONTINUE` and `ServiceAccess::QUERY_STATUS`
+    pub fn resume(&self) -> WaitResult<()> {
+        self.resume_with(&WaitOptions::default())
+    }
+
+    pub fn resume_with(&self, options: &WaitOptions) -> WaitResult<()> {
+        self.control_and_wait(SERVICE_CONTROL_CONTINUE, ServiceState::ContinuePending, ServiceState::Running, options)
+    }
+
+    /// query the current state of the service, requires
+    /// `ServiceAccess::QUERY_STATUS`
+    pub fn state(&self) -> Result<ServiceState> {
+        self.status().map(|status|status.state)
+    }
+
+    /// query the full status of the service including the id of its
+    /// process, requires `ServiceAccess::QUERY_STATUS`
+    pub fn status(&self) -> Result<Status> {
+        Status::from_raw(&self.raw_query_service_status::<SERVICE_STATUS_PROCESS>()?)
+    }
+
+    /// ask the service to report its current status to the service control
+    /// manager, requires `ServiceAccess::INTERROGATE`
+    pub fn interrogate(&self) -> Result<()> {
+        self.raw_control_service(SERVICE_CONTROL_INTERROGATE).map(|_|())
+    }
+
+    /// send a user defined control code, these must be in the range 128 to 255,
+    /// requires `ServiceAccess::USER_DEFINED_CONTROL`
+    pub fn send_control(&self, code: u8) -> Result<()> {
+        if code < 128 {
+            return Err(Error{code:ERROR_INVALID_PARAMETER});
+        }
+        self.raw_control_service(code as u32).map(|_|())
+    }
+
+    /// change when the service is started, requires
+    /// `ServiceAccess::CHANGE_CONFIG`
+    pub fn set_start_type(&self, start_type: StartType) -> Result<()> {
+        self.update(&ServiceSpec::new().start_type(start_type))
+    }
+
+    /// change the configuration of the installed service in place, keeping
+    /// everything not mentioned in `spec` such as recovery actions and
+    /// security, requires `ServiceAccess::CHANGE_CONFIG`
+    ///
+    /// A running service keeps running the old configuration until it is
+    /// restarted.
+    pub fn update(&self, spec: &ServiceSpec) -> Result<()> {
+        tracing::trace!("updating service: {:?}", spec);
+
+        let binary_path_name = spec.binary_path_name()?;
+        let service_start_name = spec.account.as_ref().map(|account|to_wstr(account.name()));
+        // built in and user accounts need a password, if only empty, when the
+        // account changes, virtual and managed accounts a NULL one
+        let password = spec.account.as_ref().and_then(Account::change_password).map(to_wstr);
+        let display_name = spec.display_name.as_ref().map(|display_name|to_wstr(display_name.as_str()));
+
+        fn ptr(value: &Option<Vec<u16>>) -> *const u16 {
+            value.as_ref().map_or(null_mut(), |value|value.as_ptr())
+        }
+
+        Error::check_true(unsafe {
+            ChangeServiceConfigW(
+                self.0,
+                SERVICE_NO_CHANGE,
+                spec.start_type.map_or(SERVICE_NO_CHANGE, StartType::into_raw),
+                SERVICE_NO_CHANGE,
+                ptr(&binary_path_name),
+                null_mut(), // lpLoadOrderGroup
+                null_mut(), // lpdwTagId
+                null_mut(), // lpDependencies
+                ptr(&service_start_name),
+                ptr(&password),
+                ptr(&display_name),
+            )
+        })
+    }
+
+    fn control_and_wait(&self, control: u32, pending: ServiceState, target: ServiceState, options: &WaitOptions) -> WaitResult<()> {
+        if !self.poll_for_state(pending, target, options)? {
+            self.raw_control_service(control)?;
+            if !self.poll_for_state(pending, target, options)? {
+                Err(WaitError::Unexpected{state: self.query_progress()?.0})
             } else {
                 Ok(())
             }
@@ -164,59 +629,75 @@ impl Service {
         }
     }
 
-    fn wait_for_start(&self)-> Result<bool> {
+    fn query_progress(&self) -> Result<(ServiceState,u32,Duration)> {
+        let status = self.status()?;
+        Ok((status.state, status.check_point, status.wait_hint))
+    }
+
+    fn wait_for_start(&self, options: &WaitOptions)-> WaitResult<bool> {
+        self.poll_for_state(ServiceState::StartPending, ServiceState::Running, options)
+    }
 
-        let sleep = |timeout|unsafe {SleepEx(timeout, 1)};
+    /// returns true once the service reaches `target`, waiting while it is in
+    /// `pending`, or false if it is in some other state
+    ///
+    /// While pending the service must advance its check point within its
+    /// wait hint, or `MIN_STALL_TIMEOUT` if that is longer, otherwise it is
+    /// considered stalled.
+    fn poll_for_state(&self, pending: ServiceState, target: ServiceState, options: &WaitOptions)-> WaitResult<bool> {
 
-        let query = || -> Result<(u32,u32)> {
-            let st = self.raw_query_service_status::<SERVICE_STATUS_PROCESS>()?;
-            Ok((st.dwCurrentState, st.dwWaitHint))
-        };
+        let started = Instant::now();
+        let deadline = options.timeout.map(|timeout|started + timeout);
 
-        let (state, wait) = query()?;
+        let (mut state, mut check_point, mut wait_hint) = self.query_progress()?;
+        let mut last_progress = started;
 
-        match state {
-            SERVICE_RUNNING => Ok(true),
-            SERVICE_START_PENDING => {
-                sleep(wait);
-                loop {
-                    let (state,wait) = query()?;
-                    match state {
-                        SERVICE_START_PENDING => {sleep(wait);continue},
-                        SERVICE_RUNNING => break Ok(true),
-                        _ => return Err(Error{code:ERROR_GEN_FAILURE}),
-                    }
-                }
-            },
-            _ => Ok(false),
+        if state == target {
+            return Ok(true);
+        } else if state != pending {
+            return Ok(false);
         }
-    }
 
-    fn wait_for_stop(&self)-> Result<bool> {
+        loop {
+            let mut wake = Instant::now() + poll_interval(wait_hint);
+            if let Some(deadline) = deadline {
+                wake = wake.min(deadline);
+            }
 
-        let sleep = |timeout|unsafe {SleepEx(timeout, 1)};
+            loop {
+                if options.is_cancelled() {
+                    return Err(WaitError::Cancelled{state});
+                }
+                let now = Instant::now();
+                if now >= wake {
+                    break;
+                }
+                let slice = (wake - now).min(CANCEL_POLL_INTERVAL);
+                unsafe { SleepEx(slice.as_millis() as u32, 1) };
+            }
 
-        let query = || -> Result<(u32,u32)> {
-            let st = self.raw_query_service_status::<SERVICE_STATUS_PROCESS>()?;
-            Ok((st.dwCurrentState, st.dwWaitHint))
-        };
+            let (new_state, new_check_point, new_wait_hint) = self.query_progress()?;
+            let now = Instant::now();
 
-        let (state, wait) = query()?;
+            if new_state == target {
+                return Ok(true);
+            } else if new_state != pending {
+                return Err(WaitError::Unexpected{state: new_state});
+            }
 
-        match state {
-            SERVICE_STOPPED => Ok(true),
-            SERVICE_STOP_PENDING => {
-                sleep(wait);
-                loop {
-                    let (state,wait) = query()?;
-                    match state {
-                        SERVICE_STOP_PENDING => {sleep(wait);continue},
-                        SERVICE_STOPPED => break Ok(true),
-                        _ => return Err(Error{code:ERROR_GEN_FAILURE}),
-                    }
-                }
-            },
-            _ => Ok(false),
+            if new_check_point != check_point {
+                last_progress = now;
+            } else if now.duration_since(last_progress) > stall_timeout(wait_hint) {
+                return Err(WaitError::Stalled{state: new_state, check_point: new_check_point, wait_hint});
+            }
+
+            if deadline.is_some_and(|deadline|now >= deadline) {
+                return Err(WaitError::Timeout{state: new_state});
+            }
+
+            state = new_state;
+            check_point = new_check_point;
+            wait_hint = new_wait_hint;
         }
     }
 }
@@ -226,62 +707,78 @@ impl ServiceControlManager {
     pub fn open_local(access: Access) -> Result<Self> {
         let handle = unsafe { OpenSCManagerW(null_mut(), null_mut(), access.into_raw()) };
         if handle != null_mut () {
-            Ok(Self(handle))
+            Ok(Self(handle, Origin::default()))
         } else {
             Err(Error::from_last())
         }
     }
 
+    /// open the service control manager on another machine, `machine` is
+    /// its name, optionally prefixed with `\\`
+    pub fn open_remote(machine: &str, access: Access) -> Result<Self> {
+        let raw_machine = to_wstr(machine);
+        let handle = unsafe { OpenSCManagerW(raw_machine.as_ptr(), null_mut(), access.into_raw()) };
+        if !handle.is_null() {
+            Ok(Self(handle, Origin{ machine: Some(machine.into()), service: None }))
+        } else {
+            Err(Error::from_last())
+        }
+    }
+
+    /// open the local service control manager if `machine` is `None`,
+    /// otherwise the one on the named machine
+    pub fn open(machine: Option<&str>, access: Access) -> Result<Self> {
+        match machine {
+            Some(machine) => Self::open_remote(machine, access),
+            None => Self::open_local(access),
+        }
+    }
+
+    /// open an existing service requesting only the rights in `access`
     pub fn open_service(
         &mut self,
-        service_name: &str,
+        service_name: &ServiceName,
+        access: ServiceAccess,
     ) -> Result<Service> {
 
-        let service_name = to_wstr(service_name);
+        let raw_service_name = to_wstr(service_name.as_str());
 
-        let handle = unsafe { OpenServiceW(self.0, service_name.as_ptr(), SC_MANAGER_ALL_ACCESS) };
+        let handle = unsafe { OpenServiceW(self.0, raw_service_name.as_ptr(), access.into_raw()) };
 
         if handle != null_mut() {
-            Ok(Service(handle))
+            Ok(Service(handle, self.origin_of(service_name)))
         } else {
             Err(Error::from_last())
         }
     }
 
-    /// create a service that starts this executable with the specified arguments
+    /// create a service that starts this executable with the specified
+    /// arguments and runs as `account`, requires `Access::CREATE_SERVICE`
     pub fn create_self_service_simple(
         &mut self,
-        service_name: &str,
-        display_name: &str,
+        service_name: &ServiceName,
+        display_name: &DisplayName,
         arguments: &[&str],
-        service_start_name: &str,
+        account: &Account,
     ) -> Result<Service> {
 
-        let mut binary_path_name = get_this_module_filename_raw()?;
-        let raw_service_name = to_wstr(service_name);
-        let display_name = to_wstr(display_name);
-        let service_start_name = to_wstr(service_start_name);
-
-        for argument in arguments {
-            tracing::trace!("arg: {}", argument);
-            assert!(!argument.contains('"'));
-            binary_path_name.push(' ' as u16);
-            let mut argument = to_wstr(&argument);
-            argument.truncate(argument.len() - 1);
-            if argument.contains(&(' ' as u16)) {
-                argument.insert(0, '"' as u16);
-                argument.push('"' as u16);
-            }
-            binary_path_name.append(&mut argument);
-        }
+        let program = get_this_module_filename_raw()?;
+        let raw_service_name = to_wstr(service_name.as_str());
+        let display_name = to_wstr(display_name.as_str());
+        let service_start_name = to_wstr(account.name());
+        let password = account.password().map(to_wstr);
+
+        let arguments: Vec<Vec<u16>> = arguments.iter()
+            .inspect(|argument|tracing::trace!("arg: {}", argument))
+            .map(|argument|argument.encode_utf16().collect())
+            .collect();
+
+        let mut binary_path_name = command_line::join_wide(&program, &arguments)
+            .map_err(|_|Error{code:ERROR_INVALID_PARAMETER})?;
         binary_path_name.push(0);
 
-        use std::ffi::OsString;
-        use std::os::windows::ffi::OsStringExt;
         tracing::trace!("binary_path_name: {:?}", OsString::from_wide(&binary_path_name));
 
-        let password: [u16;1] = [0];
-
         let handle = unsafe {
             CreateServiceW(
                 self.0,
@@ -296,7 +793,7 @@ impl ServiceControlManager {
                 null_mut(), // lpdwTagId
                 null_mut(), // lpDependencies
                 service_start_name.as_ptr(),
-                password.as_ptr(),
+                password.as_ref().map_or(null_mut(), |password|password.as_ptr()),
             )
         };
 
@@ -304,7 +801,11 @@ impl ServiceControlManager {
             return Err(Error::from_last());
         }
 
-        Ok(Service(handle))
+        Ok(Service(handle, self.origin_of(service_name)))
+    }
+
+    fn origin_of(&self, service_name: &ServiceName) -> Origin {
+        Origin{ machine: self.1.machine.clone(), service: Some(service_name.as_str().into()) }
     }
 }
 
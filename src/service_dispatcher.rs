@@ -2,13 +2,18 @@
 use std::{
     ptr::null_mut,
     sync::mpsc,
-    ffi::c_void,
+    ffi::OsString,
+    path::PathBuf,
+    os::windows::ffi::OsStringExt,
 };
 
 use winapi::{
+    ctypes::c_void,
     shared::winerror::{
         NO_ERROR,
         ERROR_CALL_NOT_IMPLEMENTED,
+        ERROR_INVALID_PARAMETER,
+        ERROR_FAILED_SERVICE_CONTROLLER_CONNECT,
     },
     um::{
         winsvc::{
@@ -19,6 +24,8 @@ use winapi::{
             SERVICE_PAUSED,
             SERVICE_STOPPED,
 
+            SERVICE_ACCEPT_PARAMCHANGE,
+
             SERVICE_CONTROL_INTERROGATE,
             SERVICE_CONTROL_PARAMCHANGE,
             SERVICE_CONTROL_PAUSE,
@@ -29,9 +36,27 @@ use winapi::{
             SERVICE_TABLE_ENTRYW,
             SERVICE_STATUS_HANDLE,
 
+            SERVICE_START_REASON_DEMAND,
+            SERVICE_START_REASON_AUTO,
+            SERVICE_START_REASON_TRIGGER,
+            SERVICE_START_REASON_RESTART_ON_FAILURE,
+            SERVICE_START_REASON_DELAYEDAUTO,
+            SERVICE_DYNAMIC_INFORMATION_LEVEL_START_REASON,
+
             SetServiceStatus,
             StartServiceCtrlDispatcherW,
             RegisterServiceCtrlHandlerExW,
+            QueryServiceDynamicInformation,
+        },
+        winbase::LocalFree,
+        handleapi::{CloseHandle,INVALID_HANDLE_VALUE},
+        processthreadsapi::{GetCurrentProcessId,ProcessIdToSessionId},
+        tlhelp32::{
+            CreateToolhelp32Snapshot,
+            Process32FirstW,
+            Process32NextW,
+            PROCESSENTRY32W,
+            TH32CS_SNAPPROCESS,
         },
         winnt::{
             SERVICE_WIN32_OWN_PROCESS,
@@ -39,21 +64,64 @@ use winapi::{
     },
 };
 
-use super::{Result, Error, to_wstr};
+use super::{Result, Error, to_wstr, get_this_module_filename};
 
 pub type RawServiceMain = unsafe extern "system" fn(u32, *mut *mut u16);
 
 pub struct ServiceArgs{
-    _argc: u32, _argv: *mut *mut u16
+    argc: u32, argv: *mut *mut u16
 }
 
 impl ServiceArgs {
-    pub unsafe fn from_raw(_argc: u32, _argv: *mut *mut u16) -> Self {
-        Self{ _argc, _argv }
+    /// wrap the arguments a service main receives
+    ///
+    /// # Safety
+    ///
+    /// `argv` must point to `argc` valid NUL-terminated UTF-16 strings that
+    /// outlive the returned value, as the service control manager passes
+    /// them to a service main.
+    pub unsafe fn from_raw(argc: u32, argv: *mut *mut u16) -> Self {
+        Self{ argc, argv }
+    }
+
+    /// the start parameters, the first is the service name followed by any
+    /// passed to `StartService`
+    pub fn arguments(&self) -> Vec<OsString> {
+        (0..self.argc as usize)
+            .map(|i| unsafe {
+                let arg = *self.argv.add(i);
+                let len = (0..).take_while(|&j|*arg.add(j) != 0).count();
+                OsString::from_wide(std::slice::from_raw_parts(arg, len))
+            })
+            .collect()
     }
 }
 
-pub struct ServiceStatus(SERVICE_STATUS_HANDLE,SERVICE_STATUS);
+/// why the service was started, more than one reason can be set
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub struct StartReason(u32);
+
+impl StartReason {
+    pub const DEMAND: Self = Self(SERVICE_START_REASON_DEMAND);
+    pub const AUTO: Self = Self(SERVICE_START_REASON_AUTO);
+    pub const TRIGGER: Self = Self(SERVICE_START_REASON_TRIGGER);
+    pub const RESTART_ON_FAILURE: Self = Self(SERVICE_START_REASON_RESTART_ON_FAILURE);
+    pub const DELAYED_AUTO: Self = Self(SERVICE_START_REASON_DELAYEDAUTO);
+
+    pub const fn from_raw(bits: u32) -> Self { Self(bits) }
+
+    pub const fn into_raw(self) -> u32 { self.0 }
+
+    pub const fn contains(self, other: Self) -> bool { self.0 & other.0 == other.0 }
+}
+
+/// the status handle, the last status and the extra controls accepted while
+/// running
+pub struct ServiceStatus(SERVICE_STATUS_HANDLE,SERVICE_STATUS,u32);
+
+/// the status handle is not closed and `SetServiceStatus` may be called from
+/// any thread, so the status can be reported from wherever the service runs
+unsafe impl Send for ServiceStatus {}
 
 #[derive(Clone,Debug)]
 pub enum ServiceEvent{
@@ -62,6 +130,7 @@ pub enum ServiceEvent{
     Pause,
     Continue,
     Stop,
+    UserDefined(u8),
 }
 
 pub const SERVICE_EVENT_NOT_IMPLEMENTED : Error = Error{code: ERROR_CALL_NOT_IMPLEMENTED};
@@ -78,6 +147,7 @@ impl ServiceEvent {
             SERVICE_CONTROL_PAUSE => Some(Self::Pause),
             SERVICE_CONTROL_CONTINUE => Some(Self::Continue),
             SERVICE_CONTROL_STOP => Some(Self::Stop),
+            128..=255 => Some(Self::UserDefined(control as u8)),
             _ => None
         }
     }
@@ -85,6 +155,21 @@ impl ServiceEvent {
 
 impl ServiceStatus {
 
+    /// query why the service was started, e.g. by one of its triggers
+    pub fn start_reason(&self) -> Result<StartReason> {
+        unsafe {
+            let mut info = null_mut();
+            Error::check_true(QueryServiceDynamicInformation(
+                self.0,
+                SERVICE_DYNAMIC_INFORMATION_LEVEL_START_REASON,
+                &mut info,
+            ))?;
+            let reason = *(info as *const u32);
+            LocalFree(info);
+            Ok(StartReason(reason))
+        }
+    }
+
     pub fn send(&mut self) -> Result<()> {
         tracing::trace!("sending service status: {}", self.1.dwCurrentState);
         let res = unsafe { SetServiceStatus(self.0, &mut self.1) };
@@ -120,10 +205,21 @@ impl ServiceStatus {
 
     pub fn running(&mut self) -> Result<()> {
         self.1.dwCurrentState = SERVICE_RUNNING;
-        self.1.dwControlsAccepted = SERVICE_CONTROL_STOP|SERVICE_CONTROL_PAUSE;
+        self.1.dwControlsAccepted = SERVICE_CONTROL_STOP|SERVICE_CONTROL_PAUSE|self.2;
         self.send()
     }
 
+    /// also accept parameter changes while running, the handler is only sent
+    /// `ParamChange` once this is called
+    pub fn accept_param_change(&mut self) -> Result<()> {
+        self.2 = SERVICE_ACCEPT_PARAMCHANGE;
+        if self.1.dwCurrentState == SERVICE_RUNNING {
+            self.running()
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn stopping(&mut self, wait_hint: std::time::Duration) ->  Result<()> {
         self.1.dwCurrentState = SERVICE_STOP_PENDING;
         self.1.dwControlsAccepted = 0;
@@ -183,7 +279,7 @@ pub fn register_service_ctrl_handler<T>(service_name: &str, handler: T) -> Resul
             dwWaitHint: 1000,
         };
 
-        Ok(ServiceStatus(handle,status))
+        Ok(ServiceStatus(handle,status,0))
     } else {
         drop(unsafe{ Box::from_raw(context) });
         Err(Error::from_last())
@@ -191,6 +287,12 @@ pub fn register_service_ctrl_handler<T>(service_name: &str, handler: T) -> Resul
 }
 
 
+/// call `function` with the arguments of a raw service main
+///
+/// # Safety
+///
+/// `argc` and `argv` must be what the service control manager passed the
+/// service main, see `ServiceArgs::from_raw`.
 pub unsafe fn raw_service_main_wrapper(
     argc: u32,
     argv: *mut *mut u16,
@@ -199,9 +301,15 @@ pub unsafe fn raw_service_main_wrapper(
     function(ServiceArgs::from_raw(argc,argv))
 }
 
+/// connect to the service control manager and run `$function` as the
+/// service, with `else` calling `$console` instead when the process was not
+/// started as a service
+///
+/// `$name` is used in the service main this defines, so it may name a
+/// constant by its full path but not through `Self`.
 #[macro_export]
 macro_rules! start_service_ctrl_dispatcher {
-    ( $name:literal => $function:ident ) => {
+    ( $name:expr => $function:ident ) => {
         {
             use $crate::service_dispatcher::{
                 start_service_ctrl_dispatcher_raw,
@@ -215,6 +323,148 @@ macro_rules! start_service_ctrl_dispatcher {
             start_service_ctrl_dispatcher_raw($name, service_main_raw)
         }
     };
+    ( $name:expr => $function:ident, $options:expr ) => {
+        {
+            use $crate::service_dispatcher::{
+                start_service_ctrl_dispatcher_with,
+                raw_service_main_wrapper,
+            };
+
+            unsafe extern "system" fn service_main_raw(argc: u32, argv: *mut *mut u16) {
+                raw_service_main_wrapper(argc,argv,$function);
+            }
+
+            start_service_ctrl_dispatcher_with($name, service_main_raw, &$options)
+        }
+    };
+    ( $name:expr => $function:ident, $options:expr, else $console:expr ) => {
+        {
+            use $crate::service_dispatcher::{
+                start_service_ctrl_dispatcher_or_else,
+                raw_service_main_wrapper,
+            };
+
+            unsafe extern "system" fn service_main_raw(argc: u32, argv: *mut *mut u16) {
+                raw_service_main_wrapper(argc,argv,$function);
+            }
+
+            start_service_ctrl_dispatcher_or_else($name, service_main_raw, &$options, $console)
+        }
+    };
+}
+
+/// the working directory of the service process, which the service control
+/// manager starts in the system directory
+#[derive(Clone,Debug,Default)]
+pub enum WorkingDirectory {
+    #[default]
+    Unchanged,
+    /// the directory containing this executable
+    ExecutableDirectory,
+    Path(PathBuf),
+}
+
+/// options applied before the service main runs, or before the console
+/// fallback of `start_service_ctrl_dispatcher_or_else`
+#[derive(Clone,Debug,Default)]
+pub struct DispatcherOptions {
+    pub working_directory: WorkingDirectory,
+}
+
+impl DispatcherOptions {
+    pub fn working_directory(mut self, working_directory: WorkingDirectory) -> Self {
+        self.working_directory = working_directory;
+        self
+    }
+
+    fn apply(&self) -> Result<()> {
+        let directory = match &self.working_directory {
+            WorkingDirectory::Unchanged => return Ok(()),
+            WorkingDirectory::ExecutableDirectory => {
+                let mut path = get_this_module_filename()?;
+                path.pop();
+                path
+            },
+            WorkingDirectory::Path(path) => path.clone(),
+        };
+        tracing::trace!("setting working directory: {:?}", directory);
+        std::env::set_current_dir(&directory)
+            .map_err(|error|Error{code: error.raw_os_error().unwrap_or(ERROR_INVALID_PARAMETER as i32) as u32})
+    }
+}
+
+/// true if the service control manager launched this process
+///
+/// Services run in session 0 as children of `services.exe`, a process started
+/// from a console is in an interactive session or has another parent.
+pub fn is_running_as_service() -> bool {
+    unsafe {
+        let process_id = GetCurrentProcessId();
+
+        let mut session_id = 0;
+        if ProcessIdToSessionId(process_id, &mut session_id) == 0 || session_id != 0 {
+            return false;
+        }
+
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+        if snapshot == INVALID_HANDLE_VALUE {
+            return false;
+        }
+
+        let mut processes = Vec::new();
+        let mut entry: PROCESSENTRY32W = std::mem::zeroed();
+        entry.dwSize = std::mem::size_of::<PROCESSENTRY32W>() as u32;
+        let mut more = Process32FirstW(snapshot, &mut entry);
+        while more != 0 {
+            let len = entry.szExeFile.iter().position(|c|*c == 0).unwrap_or(entry.szExeFile.len());
+            let name = OsString::from_wide(&entry.szExeFile[..len]);
+            processes.push((entry.th32ProcessID, entry.th32ParentProcessID, name));
+            more = Process32NextW(snapshot, &mut entry);
+        }
+        CloseHandle(snapshot);
+
+        let parent_id = processes.iter()
+            .find(|(id,_,_)|*id == process_id)
+            .map(|(_,parent_id,_)|*parent_id);
+
+        processes.iter()
+            .any(|(id,_,name)| Some(*id) == parent_id && name.to_string_lossy().eq_ignore_ascii_case("services.exe"))
+    }
+}
+
+/// apply `options`, then run as a service if the service control manager
+/// launched this process, otherwise call `console` to run in the foreground
+/// instead
+///
+/// The options apply in both modes, so relative paths resolve the same way.
+pub fn start_service_ctrl_dispatcher_or_else(
+    service_name: &str,
+    service_main: RawServiceMain,
+    options: &DispatcherOptions,
+    console: impl FnOnce(),
+) -> Result<()> {
+    options.apply()?;
+
+    if !is_running_as_service() {
+        tracing::trace!("not started by the service control manager, running in console mode");
+        console();
+        return Ok(());
+    }
+
+    match start_service_ctrl_dispatcher_raw(service_name, service_main) {
+        Err(Error{code: ERROR_FAILED_SERVICE_CONTROLLER_CONNECT}) => {
+            tracing::trace!("could not connect to the service control manager, running in console mode");
+            console();
+            Ok(())
+        },
+        result => result,
+    }
+}
+
+/// apply `options` and then connect to the service control manager
+pub fn start_service_ctrl_dispatcher_with(service_name: &str, service_main: RawServiceMain, options: &DispatcherOptions) -> Result<()> {
+    options.apply()?;
+    start_service_ctrl_dispatcher_raw(service_name, service_main)
 }
 
 pub fn start_service_ctrl_dispatcher_raw(service_name: &str, service_main: RawServiceMain) -> Result<()> {
@@ -0,0 +1,172 @@
+//! Validated service and display names
+//!
+//! The service control manager limits both names to 256 characters and
+//! compares service names without regard to case. Service names also become
+//! a registry key under `SYSTEM\CurrentControlSet\Services`, so they can not
+//! contain path separators.
+
+use std::{
+    fmt,
+    hash::{Hash,Hasher},
+    str::FromStr,
+};
+
+/// the maximum length of service and display names in UTF-16 code units
+pub const MAX_NAME_LEN: usize = 256;
+
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub enum NameError {
+    Empty,
+    TooLong{len: usize},
+    ForbiddenCharacter(char),
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NameError::Empty => f.write_str("name is empty"),
+            NameError::TooLong{len} => write!(f, "name is {} characters long, the limit is {}", len, MAX_NAME_LEN),
+            NameError::ForbiddenCharacter(c) => write!(f, "name contains {:?}", c),
+        }
+    }
+}
+
+impl std::error::Error for NameError {}
+
+fn check_len(name: &str) -> Result<(),NameError> {
+    let len = name.encode_utf16().count();
+    if len == 0 {
+        Err(NameError::Empty)
+    } else if len > MAX_NAME_LEN {
+        Err(NameError::TooLong{len})
+    } else {
+        Ok(())
+    }
+}
+
+/// the name identifying a service, compared case insensitively
+#[derive(Clone,Debug)]
+pub struct ServiceName(String);
+
+impl ServiceName {
+    pub fn new(name: impl Into<String>) -> Result<Self,NameError> {
+        let name = name.into();
+        check_len(&name)?;
+        if let Some(c) = name.chars().find(|c|matches!(c, '/' | '\\') || c.is_control()) {
+            return Err(NameError::ForbiddenCharacter(c));
+        }
+        Ok(Self(name))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn folded(&self) -> String {
+        self.0.to_uppercase()
+    }
+}
+
+impl PartialEq for ServiceName {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0 || self.folded() == other.folded()
+    }
+}
+
+impl Eq for ServiceName {}
+
+impl Hash for ServiceName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.folded().hash(state)
+    }
+}
+
+impl FromStr for ServiceName {
+    type Err = NameError;
+    fn from_str(name: &str) -> Result<Self,NameError> {
+        Self::new(name)
+    }
+}
+
+impl AsRef<str> for ServiceName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ServiceName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// the name shown to users for a service
+#[derive(Clone,Debug,Eq,PartialEq,Hash)]
+pub struct DisplayName(String);
+
+impl DisplayName {
+    pub fn new(name: impl Into<String>) -> Result<Self,NameError> {
+        let name = name.into();
+        check_len(&name)?;
+        if let Some(c) = name.chars().find(|c|*c == '\0') {
+            return Err(NameError::ForbiddenCharacter(c));
+        }
+        Ok(Self(name))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for DisplayName {
+    type Err = NameError;
+    fn from_str(name: &str) -> Result<Self,NameError> {
+        Self::new(name)
+    }
+}
+
+impl AsRef<str> for DisplayName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for DisplayName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn service_names() {
+        assert!(ServiceName::new("winsvc-test-service-1").is_ok());
+        assert_eq!(ServiceName::new(""), Err(NameError::Empty));
+        assert_eq!(ServiceName::new("a/b"), Err(NameError::ForbiddenCharacter('/')));
+        assert_eq!(ServiceName::new("a\\b"), Err(NameError::ForbiddenCharacter('\\')));
+        assert_eq!(ServiceName::new("a\0b"), Err(NameError::ForbiddenCharacter('\0')));
+        assert!(ServiceName::new("x".repeat(MAX_NAME_LEN)).is_ok());
+        assert_eq!(ServiceName::new("x".repeat(MAX_NAME_LEN + 1)), Err(NameError::TooLong{len: MAX_NAME_LEN + 1}));
+    }
+
+    #[test]
+    fn service_names_ignore_case() {
+        let lower: ServiceName = "winsvc".parse().unwrap();
+        let upper: ServiceName = "WinSvc".parse().unwrap();
+        assert_eq!(lower, upper);
+        assert_eq!(upper.as_str(), "WinSvc");
+        assert_eq!(vec![lower, upper].into_iter().collect::<HashSet<_>>().len(), 1);
+    }
+
+    #[test]
+    fn display_names() {
+        assert!(DisplayName::new("WinSvc Test Service / 1").is_ok());
+        assert_eq!(DisplayName::new(""), Err(NameError::Empty));
+        assert!(DisplayName::new("x".repeat(MAX_NAME_LEN + 1)).is_err());
+    }
+}
@@ -0,0 +1,435 @@
+//! Event driven service status notifications
+//!
+//! `NotifyServiceStatusChangeW` delivers its notifications as APCs to the
+//! thread that registered them, and only while that thread is in an alertable
+//! wait. Each wait here therefore owns a small worker thread that registers
+//! the notification and sleeps alertably until it fires, handing the result
+//! back to a runtime agnostic future.
+//!
+//! A registration can only be cancelled by closing its handle, and its
+//! `SERVICE_NOTIFYW` must stay valid until then. Workers register on a handle
+//! they open themselves and close it before they return, so a wait that is
+//! dropped or times out leaves nothing behind on the caller's handle.
+
+use std::{
+    mem::zeroed,
+    ptr::null,
+    future::Future,
+    pin::Pin,
+    collections::VecDeque,
+    ffi::OsString,
+    os::windows::{
+        ffi::OsStringExt,
+        io::AsRawHandle,
+    },
+    sync::{Arc,Mutex,atomic::{AtomicBool,Ordering::SeqCst}},
+    task::{Context,Poll,Waker},
+    thread::{self,JoinHandle},
+    time::{Duration,Instant},
+};
+
+use winapi::{
+    ctypes::c_void,
+    shared::{
+        basetsd::ULONG_PTR,
+        winerror::{ERROR_SERVICE_MARKED_FOR_DELETE,ERROR_OPERATION_ABORTED},
+    },
+    um::{
+        synchapi::SleepEx,
+        processthreadsapi::QueueUserAPC,
+        winbase::{LocalFree,INFINITE},
+        winsvc::{
+            NotifyServiceStatusChangeW,
+            OpenSCManagerW,
+            OpenServiceW,
+            CloseServiceHandle,
+            SC_HANDLE,
+            SC_MANAGER_CONNECT,
+            SC_MANAGER_ENUMERATE_SERVICE,
+            SERVICE_QUERY_STATUS,
+            SERVICE_NOTIFYW,
+            SERVICE_NOTIFY_STATUS_CHANGE,
+            SERVICE_NOTIFY_STOPPED,
+            SERVICE_NOTIFY_START_PENDING,
+            SERVICE_NOTIFY_STOP_PENDING,
+            SERVICE_NOTIFY_RUNNING,
+            SERVICE_NOTIFY_CONTINUE_PENDING,
+            SERVICE_NOTIFY_PAUSE_PENDING,
+            SERVICE_NOTIFY_PAUSED,
+            SERVICE_NOTIFY_CREATED,
+            SERVICE_NOTIFY_DELETED,
+            SERVICE_NOTIFY_DELETE_PENDING,
+        },
+    },
+};
+
+use crate::{
+    Error, Result, to_wstr,
+    service_control_manager::{
+        Service, ServiceControlManager, ServiceState, Origin,
+        WaitError, WaitResult,
+    },
+};
+
+/// a service was created or deleted
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub enum ServiceChange {
+    Created(String),
+    Deleted(String),
+}
+
+impl ServiceState {
+    fn notify_mask(self) -> u32 {
+        match self {
+            Self::Stopped => SERVICE_NOTIFY_STOPPED,
+            Self::StartPending => SERVICE_NOTIFY_START_PENDING,
+            Self::StopPending => SERVICE_NOTIFY_STOP_PENDING,
+            Self::Running => SERVICE_NOTIFY_RUNNING,
+            Self::ContinuePending => SERVICE_NOTIFY_CONTINUE_PENDING,
+            Self::PausePending => SERVICE_NOTIFY_PAUSE_PENDING,
+            Self::Paused => SERVICE_NOTIFY_PAUSED,
+        }
+    }
+}
+
+/// queue shared between a worker thread and the future polling it
+pub(crate) struct Slot<T> {
+    items: VecDeque<T>,
+    waker: Option<Waker>,
+    /// the worker is done, nothing more will be pushed
+    closed: bool,
+}
+
+pub(crate) type Shared<T> = Arc<Mutex<Slot<T>>>;
+
+/// the worker's end of a `Shared`, which closes it when dropped, however the
+/// worker ends
+pub(crate) struct Sender<T>(Shared<T>);
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut slot = self.0.lock().unwrap();
+        slot.closed = true;
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+pub(crate) fn shared<T>() -> (Shared<T>, Sender<T>) {
+    let shared = Arc::new(Mutex::new(Slot{ items: VecDeque::new(), waker: None, closed: false }));
+    (shared.clone(), Sender(shared))
+}
+
+pub(crate) fn push<T>(sender: &Sender<T>, item: T) {
+    let mut slot = sender.0.lock().unwrap();
+    slot.items.push_back(item);
+    if let Some(waker) = slot.waker.take() {
+        waker.wake();
+    }
+}
+
+/// the next item, `None` once the worker has finished and every item it
+/// pushed has been taken
+pub(crate) fn poll_pop<T>(shared: &Shared<T>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+    let mut slot = shared.lock().unwrap();
+    match slot.items.pop_front() {
+        Some(item) => Poll::Ready(Some(item)),
+        None if slot.closed => Poll::Ready(None),
+        None => {
+            slot.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// owns a notification worker thread, stopping and joining it when dropped
+pub(crate) struct Worker {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+unsafe extern "system" fn wake_apc(_: ULONG_PTR) {}
+
+impl Worker {
+    pub(crate) fn spawn(body: impl FnOnce(&AtomicBool) + Send + 'static) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            thread::spawn(move || body(&stop))
+        };
+        Self{ stop, thread: Some(thread) }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.stop.store(true, SeqCst);
+        if let Some(thread) = self.thread.take() {
+            // an empty APC pulls the worker out of its alertable sleep
+            unsafe { QueueUserAPC(Some(wake_apc), thread.as_raw_handle() as _, 0) };
+            let _ = thread.join();
+        }
+    }
+}
+
+/// a handle a worker opens for its registrations, closing it cancels one
+/// that has not fired
+struct NotifyHandle(SC_HANDLE);
+
+impl NotifyHandle {
+    /// open the service control manager or the service `origin` names, with
+    /// just the access notifications need
+    fn open(origin: &Origin) -> Result<Self> {
+        let machine = origin.machine.as_ref().map(to_wstr);
+        let machine = machine.as_ref().map_or(null(), |machine|machine.as_ptr());
+        let access = if origin.service.is_some() { SC_MANAGER_CONNECT } else { SC_MANAGER_ENUMERATE_SERVICE };
+        let scm = unsafe { OpenSCManagerW(machine, null(), access) };
+        if scm.is_null() {
+            return Err(Error::from_last());
+        }
+        let scm = NotifyHandle(scm);
+        match &origin.service {
+            Some(service) => {
+                let service = to_wstr(service);
+                let handle = unsafe { OpenServiceW(scm.0, service.as_ptr(), SERVICE_QUERY_STATUS) };
+                if handle.is_null() {
+                    Err(Error::from_last())
+                } else {
+                    Ok(NotifyHandle(handle))
+                }
+            },
+            None => Ok(scm),
+        }
+    }
+}
+
+impl Drop for NotifyHandle {
+    fn drop(&mut self) {
+        unsafe { CloseServiceHandle(self.0) };
+    }
+}
+
+/// what the service control manager writes to, which must outlive the
+/// `NotifyHandle` it is registered on
+struct Registration {
+    notify: SERVICE_NOTIFYW,
+    fired: AtomicBool,
+}
+
+impl Registration {
+    fn new() -> Self {
+        Self{ notify: unsafe { zeroed() }, fired: AtomicBool::new(false) }
+    }
+}
+
+unsafe extern "system" fn notify_callback(parameter: *mut c_void) {
+    let notify = &*(parameter as *const SERVICE_NOTIFYW);
+    (*(notify.pContext as *const AtomicBool)).store(true, SeqCst);
+}
+
+/// register for `mask` on `handle` and sleep alertably until it fires, the
+/// deadline passes or `stop` is set
+///
+/// Returns `None` if the notification did not fire, it is then still
+/// registered and `registration` must not be reused or dropped before
+/// `handle` is closed.
+unsafe fn notify_once(
+    handle: &NotifyHandle,
+    registration: &mut Registration,
+    mask: u32,
+    deadline: Option<Instant>,
+    stop: &AtomicBool,
+) -> Option<Result<SERVICE_NOTIFYW>> {
+
+    registration.fired.store(false, SeqCst);
+    registration.notify = zeroed();
+    registration.notify.dwVersion = SERVICE_NOTIFY_STATUS_CHANGE;
+    registration.notify.pfnNotifyCallback = Some(notify_callback);
+    registration.notify.pContext = &registration.fired as *const _ as *mut _;
+
+    if let Err(error) = Error::check_code(NotifyServiceStatusChangeW(handle.0, mask, &mut registration.notify) as i32) {
+        return Some(Err(error));
+    }
+
+    loop {
+        if registration.fired.load(SeqCst) {
+            let notify = registration.notify;
+            break Some(Error::check_code(notify.dwNotificationStatus as i32).map(|_|notify));
+        }
+
+        if stop.load(SeqCst) {
+            break None;
+        }
+
+        let timeout = match deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    break None;
+                }
+                (deadline - now).as_millis().min((INFINITE - 1) as u128) as u32
+            },
+            None => INFINITE,
+        };
+
+        SleepEx(timeout, 1);
+    }
+}
+
+/// the states a service rests in, a wait ends in any of them
+const SETTLED_MASK: u32 = SERVICE_NOTIFY_STOPPED | SERVICE_NOTIFY_RUNNING | SERVICE_NOTIFY_PAUSED;
+
+enum StatusOutcome {
+    Reached(ServiceState),
+    Failed(Error),
+    TimedOut,
+}
+
+/// future returned by `Service::wait_for_state`
+pub struct StatusWait<'a> {
+    service: &'a Service,
+    shared: Shared<StatusOutcome>,
+    _worker: Worker,
+}
+
+impl<'a> Future for StatusWait<'a> {
+    type Output = WaitResult<ServiceState>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        poll_pop(&self.shared, cx).map(|outcome| match outcome {
+            Some(StatusOutcome::Reached(state)) => Ok(state),
+            Some(StatusOutcome::Failed(error)) => Err(WaitError::System(error)),
+            Some(StatusOutcome::TimedOut) => Err(WaitError::Timeout{state: self.service.state()?}),
+            // the worker only ends without an outcome if it panicked
+            None => Err(WaitError::System(Error{code:ERROR_OPERATION_ABORTED})),
+        })
+    }
+}
+
+impl Service {
+    /// wait, without polling, for the service to enter `target` or to settle
+    /// in some other state
+    ///
+    /// Resolves to the state reached, which is not `target` if the service
+    /// stopped, started or paused instead, e.g. a failed start that went back
+    /// to `Stopped`. Completes immediately if the service is already in one of
+    /// those states. Fails if the service is marked for deletion while
+    /// waiting.
+    ///
+    /// Waits on a handle of its own opened with `ServiceAccess::QUERY_STATUS`,
+    /// whatever access this one has.
+    ///
+    /// Each pending wait costs a thread and a connection to the service
+    /// control manager until it completes or is dropped, so waiting on
+    /// hundreds of services at once means hundreds of threads. Bound the
+    /// number of waits in flight when watching that many.
+    pub fn wait_for_state(&self, target: ServiceState, timeout: Option<Duration>) -> StatusWait<'_> {
+        let (shared, sender) = shared();
+        let deadline = timeout.map(|timeout|Instant::now() + timeout);
+        let origin = self.1.clone();
+        let worker = Worker::spawn(move |stop| {
+            // declared first so it is dropped after the handle
+            let mut registration = Registration::new();
+            let handle = match NotifyHandle::open(&origin) {
+                Ok(handle) => handle,
+                Err(error) => return push(&sender, StatusOutcome::Failed(error)),
+            };
+            let mask = target.notify_mask() | SETTLED_MASK | SERVICE_NOTIFY_DELETE_PENDING;
+            let outcome = match unsafe { notify_once(&handle, &mut registration, mask, deadline, stop) } {
+                Some(Ok(notify)) => {
+                    if notify.dwNotificationTriggered & SERVICE_NOTIFY_DELETE_PENDING != 0 {
+                        StatusOutcome::Failed(Error{code:ERROR_SERVICE_MARKED_FOR_DELETE})
+                    } else {
+                        match ServiceState::from_raw(notify.ServiceStatus.dwCurrentState) {
+                            Ok(state) => StatusOutcome::Reached(state),
+                            Err(error) => StatusOutcome::Failed(error),
+                        }
+                    }
+                },
+                Some(Err(error)) => StatusOutcome::Failed(error),
+                None if stop.load(SeqCst) => return,
+                None => StatusOutcome::TimedOut,
+            };
+            push(&sender, outcome);
+        });
+        StatusWait{ service: self, shared, _worker: worker }
+    }
+}
+
+/// stream of service creation and deletion notifications
+pub struct ServiceChanges<'a> {
+    _scm: &'a ServiceControlManager,
+    shared: Shared<Result<ServiceChange>>,
+    _worker: Worker,
+}
+
+impl<'a> ServiceChanges<'a> {
+    /// wait for the next service to be created or deleted, `None` once the
+    /// watch has ended after reporting an error
+    pub async fn next(&mut self) -> Option<Result<ServiceChange>> {
+        std::future::poll_fn(|cx|self.poll_next(cx)).await
+    }
+
+    /// poll for the next change as a stream would, see `next`
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<ServiceChange>>> {
+        poll_pop(&self.shared, cx)
+    }
+}
+
+/// split the multi string of names reported for creation and deletion
+/// notifications, created services are prefixed with `/` and deleted ones
+/// with `\`
+unsafe fn take_service_names(names: *mut u16) -> Vec<ServiceChange> {
+    let mut changes = Vec::new();
+    let mut cursor = names;
+    loop {
+        let len = (0..).take_while(|&i|*cursor.add(i) != 0).count();
+        if len == 0 {
+            break;
+        }
+        let name = OsString::from_wide(std::slice::from_raw_parts(cursor, len)).to_string_lossy().into_owned();
+        match name.chars().next() {
+            Some('/') => changes.push(ServiceChange::Created(name[1..].into())),
+            Some('\\') => changes.push(ServiceChange::Deleted(name[1..].into())),
+            _ => tracing::warn!("unexpected service notification name: {:?}", name),
+        }
+        cursor = cursor.add(len + 1);
+    }
+    LocalFree(names as *mut _);
+    changes
+}
+
+impl ServiceControlManager {
+    /// receive notifications as services are created and deleted, on a
+    /// handle of its own opened with `Access::ENUMERATE_SERVICE`
+    pub fn watch_services(&self) -> ServiceChanges<'_> {
+        let (shared, sender) = shared();
+        let origin = self.1.clone();
+        let worker = Worker::spawn(move |stop| {
+            // declared first so it is dropped after the handle
+            let mut registration = Registration::new();
+            let handle = match NotifyHandle::open(&origin) {
+                Ok(handle) => handle,
+                Err(error) => return push(&sender, Err(error)),
+            };
+            let mask = SERVICE_NOTIFY_CREATED | SERVICE_NOTIFY_DELETED;
+            while let Some(result) = unsafe { notify_once(&handle, &mut registration, mask, None, stop) } {
+                match result {
+                    Ok(notify) => {
+                        if !notify.pszServiceNames.is_null() {
+                            for change in unsafe { take_service_names(notify.pszServiceNames) } {
+                                push(&sender, Ok(change));
+                            }
+                        }
+                    },
+                    Err(error) => {
+                        push(&sender, Err(error));
+                        break;
+                    },
+                }
+            }
+        });
+        ServiceChanges{ _scm: self, shared, _worker: worker }
+    }
+}
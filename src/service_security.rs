@@ -0,0 +1,139 @@
+//! Reading and writing the security descriptor of a service
+//!
+//! The binary descriptor is converted to and from SDDL by the system and the
+//! text is handled by the portable `sddl` module.
+
+use std::{
+    ptr::null_mut,
+    ffi::OsString,
+    os::windows::ffi::OsStringExt,
+};
+
+use winapi::{
+    shared::{
+        sddl::{
+            SDDL_REVISION_1,
+            ConvertSecurityDescriptorToStringSecurityDescriptorW,
+            ConvertStringSecurityDescriptorToSecurityDescriptorW,
+        },
+        winerror::{
+            ERROR_INVALID_DATA,
+            ERROR_INSUFFICIENT_BUFFER,
+        },
+    },
+    um::{
+        winbase::LocalFree,
+        winnt::{
+            PSECURITY_DESCRIPTOR,
+            OWNER_SECURITY_INFORMATION,
+            GROUP_SECURITY_INFORMATION,
+            DACL_SECURITY_INFORMATION,
+            SACL_SECURITY_INFORMATION,
+        },
+        winsvc::{
+            QueryServiceObjectSecurity,
+            SetServiceObjectSecurity,
+        },
+    },
+};
+
+use crate::{
+    Error, Result, to_wstr,
+    sddl::SecurityDescriptor,
+    service_control_manager::Service,
+};
+
+impl Service {
+    /// read the owner, group and DACL of the service, requires
+    /// `ServiceAccess::READ_CONTROL`
+    ///
+    /// Fails with `ERROR_INVALID_DATA` if the `sddl` parser does not accept
+    /// the text the system produces, `security_sddl` returns it as is.
+    pub fn security(&self) -> Result<SecurityDescriptor> {
+        let sddl = self.security_sddl()?;
+        sddl.parse().map_err(|error|{
+            tracing::warn!("unsupported service security {:?}: {}", sddl, error);
+            Error{code:ERROR_INVALID_DATA}
+        })
+    }
+
+    /// the owner, group and DACL of the service as SDDL text, requires
+    /// `ServiceAccess::READ_CONTROL`
+    pub fn security_sddl(&self) -> Result<String> {
+        let information = OWNER_SECURITY_INFORMATION | GROUP_SECURITY_INFORMATION | DACL_SECURITY_INFORMATION;
+
+        unsafe {
+            let mut bytes_needed = 0;
+
+            if QueryServiceObjectSecurity(self.0, information, null_mut(), 0, &mut bytes_needed) == 0 {
+                let error = Error::from_last();
+                if error.code != ERROR_INSUFFICIENT_BUFFER {
+                    return Err(error);
+                }
+            }
+
+            let mut buffer = vec![0u8; bytes_needed as usize];
+
+            Error::check_true(QueryServiceObjectSecurity(
+                self.0,
+                information,
+                buffer.as_mut_ptr() as PSECURITY_DESCRIPTOR,
+                buffer.len() as u32,
+                &mut bytes_needed,
+            ))?;
+
+            let mut string = null_mut();
+            let mut string_len = 0;
+
+            Error::check_true(ConvertSecurityDescriptorToStringSecurityDescriptorW(
+                buffer.as_mut_ptr() as PSECURITY_DESCRIPTOR,
+                SDDL_REVISION_1 as u32,
+                information,
+                &mut string,
+                &mut string_len,
+            ))?;
+
+            let sddl = OsString::from_wide(std::slice::from_raw_parts(string, string_len as usize));
+            LocalFree(string as *mut _);
+
+            let sddl = sddl.to_string_lossy();
+            tracing::trace!("service security: {}", sddl);
+
+            Ok(sddl.trim_end_matches('\0').to_string())
+        }
+    }
+
+    /// replace the parts of the security descriptor present in `security`
+    ///
+    /// Requires `ServiceAccess::WRITE_DAC` for the DACL,
+    /// `ServiceAccess::WRITE_OWNER` for the owner and group and
+    /// `ServiceAccess::ACCESS_SYSTEM_SECURITY` for the SACL.
+    pub fn set_security(&self, security: &SecurityDescriptor) -> Result<()> {
+        let mut information = 0;
+        if security.owner.is_some() { information |= OWNER_SECURITY_INFORMATION }
+        if security.group.is_some() { information |= GROUP_SECURITY_INFORMATION }
+        if security.dacl.is_some() { information |= DACL_SECURITY_INFORMATION }
+        if security.sacl.is_some() { information |= SACL_SECURITY_INFORMATION }
+
+        let sddl = security.to_string();
+        tracing::trace!("setting service security: {}", sddl);
+        let sddl = to_wstr(sddl);
+
+        unsafe {
+            let mut descriptor: PSECURITY_DESCRIPTOR = null_mut();
+
+            Error::check_true(ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                sddl.as_ptr(),
+                SDDL_REVISION_1 as u32,
+                &mut descriptor,
+                null_mut(),
+            ))?;
+
+            let result = Error::check_true(SetServiceObjectSecurity(self.0, information, descriptor));
+
+            LocalFree(descriptor);
+
+            result
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Service start triggers
+//!
+//! A trigger lets the system start or stop a service when an event happens,
+//! such as a network address becoming available, instead of keeping it
+//! running from boot. The service can find out why it was started with
+//! `ServiceStatus::start_reason`.
+
+#[cfg(windows)]
+use std::ptr::null_mut;
+
+#[cfg(windows)]
+use winapi::{
+    shared::guiddef::GUID,
+    um::winsvc::{
+        ChangeServiceConfig2W,
+        SERVICE_CONFIG_TRIGGER_INFO,
+        SERVICE_TRIGGER_TYPE_DEVICE_INTERFACE_ARRIVAL,
+        SERVICE_TRIGGER_TYPE_IP_ADDRESS_AVAILABILITY,
+        SERVICE_TRIGGER_TYPE_DOMAIN_JOIN,
+        SERVICE_TRIGGER_TYPE_FIREWALL_PORT_EVENT,
+        SERVICE_TRIGGER_TYPE_GROUP_POLICY,
+        SERVICE_TRIGGER_TYPE_NETWORK_ENDPOINT,
+        SERVICE_TRIGGER_TYPE_CUSTOM_SYSTEM_STATE_CHANGE,
+        SERVICE_TRIGGER_TYPE_CUSTOM,
+        NETWORK_MANAGER_FIRST_IP_ADDRESS_ARRIVAL_GUID,
+        NETWORK_MANAGER_LAST_IP_ADDRESS_REMOVAL_GUID,
+        DOMAIN_JOIN_GUID,
+        DOMAIN_LEAVE_GUID,
+        FIREWALL_PORT_OPEN_GUID,
+        FIREWALL_PORT_CLOSE_GUID,
+        MACHINE_POLICY_PRESENT_GUID,
+        USER_POLICY_PRESENT_GUID,
+        RPC_INTERFACE_EVENT_GUID,
+        NAMED_PIPE_EVENT_GUID,
+        CUSTOM_SYSTEM_STATE_CHANGE_EVENT_GUID,
+    },
+};
+
+#[cfg(windows)]
+use crate::{
+    Error, Result,
+    service_control_manager::Service,
+};
+
+// not defined by winapi
+#[cfg(windows)]
+const SERVICE_TRIGGER_ACTION_SERVICE_START: u32 = 1;
+#[cfg(windows)]
+const SERVICE_TRIGGER_ACTION_SERVICE_STOP: u32 = 2;
+
+// spelled out so the data encoding builds on every platform
+const SERVICE_TRIGGER_DATA_TYPE_BINARY: u32 = 1;
+const SERVICE_TRIGGER_DATA_TYPE_STRING: u32 = 2;
+const SERVICE_TRIGGER_DATA_TYPE_LEVEL: u32 = 3;
+const SERVICE_TRIGGER_DATA_TYPE_KEYWORD_ANY: u32 = 4;
+const SERVICE_TRIGGER_DATA_TYPE_KEYWORD_ALL: u32 = 5;
+
+#[cfg(windows)]
+#[repr(C)]
+#[allow(non_snake_case)]
+struct SERVICE_TRIGGER_SPECIFIC_DATA_ITEM {
+    dwDataType: u32,
+    cbData: u32,
+    pData: *mut u8,
+}
+
+#[cfg(windows)]
+#[repr(C)]
+#[allow(non_snake_case)]
+struct SERVICE_TRIGGER {
+    dwTriggerType: u32,
+    dwAction: u32,
+    pTriggerSubtype: *mut GUID,
+    cDataItems: u32,
+    pDataItems: *mut SERVICE_TRIGGER_SPECIFIC_DATA_ITEM,
+}
+
+#[cfg(windows)]
+#[repr(C)]
+#[allow(non_snake_case)]
+struct SERVICE_TRIGGER_INFO {
+    cTriggers: u32,
+    pTriggers: *mut SERVICE_TRIGGER,
+    pReserved: *mut u8,
+}
+
+/// a GUID identifying a device interface class or ETW provider
+#[derive(Copy,Clone,Debug,Eq,PartialEq,Hash)]
+pub struct Guid(pub u128);
+
+impl Guid {
+    /// create from the usual hex form, `0x4f27f2de_14e2_430b_a549_7cd48cbc8245`
+    pub const fn from_u128(value: u128) -> Self { Self(value) }
+
+    /// the fields of a `GUID`, the last eight bytes in the order written
+    #[cfg_attr(not(windows), allow(dead_code))]
+    fn fields(self) -> (u32, u16, u16, [u8;8]) {
+        let bytes = self.0.to_be_bytes();
+        let mut data4 = [0u8;8];
+        data4.copy_from_slice(&bytes[8..]);
+        ((self.0 >> 96) as u32, (self.0 >> 80) as u16, (self.0 >> 64) as u16, data4)
+    }
+
+    #[cfg(windows)]
+    fn into_raw(self) -> GUID {
+        let (data1, data2, data3, data4) = self.fields();
+        GUID{ Data1: data1, Data2: data2, Data3: data3, Data4: data4 }
+    }
+}
+
+/// what the system does when a trigger fires
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub enum TriggerAction {
+    Start,
+    Stop,
+}
+
+/// the event a trigger waits for
+#[derive(Copy,Clone,Debug,Eq,PartialEq)]
+pub enum TriggerEvent {
+    /// the first IP address on the machine became available
+    FirstIpAddressArrival,
+    /// the last IP address on the machine was removed
+    LastIpAddressRemoval,
+    DomainJoin,
+    DomainLeave,
+    /// a firewall port was opened, data is `port;protocol` strings
+    FirewallPortOpen,
+    FirewallPortClose,
+    /// machine group policy changed or was present at boot
+    MachinePolicy,
+    /// user group policy changed or was present at logon
+    UserPolicy,
+    /// a request arrived for an RPC interface, data is the interface UUID
+    RpcInterface,
+    /// a request arrived for a named pipe, data is the pipe name
+    NamedPipe,
+    /// a device of the interface class arrived, data is the hardware IDs
+    DeviceInterfaceArrival(Guid),
+    /// a custom system state changed, data is the state name
+    CustomSystemStateChange,
+    /// an event from the ETW provider, data filters by level and keywords
+    Custom(Guid),
+}
+
+#[cfg(windows)]
+impl TriggerEvent {
+    fn into_raw(self) -> (u32, GUID) {
+        use TriggerEvent::*;
+        match self {
+            FirstIpAddressArrival => (SERVICE_TRIGGER_TYPE_IP_ADDRESS_AVAILABILITY, NETWORK_MANAGER_FIRST_IP_ADDRESS_ARRIVAL_GUID),
+            LastIpAddressRemoval => (SERVICE_TRIGGER_TYPE_IP_ADDRESS_AVAILABILITY, NETWORK_MANAGER_LAST_IP_ADDRESS_REMOVAL_GUID),
+            DomainJoin => (SERVICE_TRIGGER_TYPE_DOMAIN_JOIN, DOMAIN_JOIN_GUID),
+            DomainLeave => (SERVICE_TRIGGER_TYPE_DOMAIN_JOIN, DOMAIN_LEAVE_GUID),
+            FirewallPortOpen => (SERVICE_TRIGGER_TYPE_FIREWALL_PORT_EVENT, FIREWALL_PORT_OPEN_GUID),
+            FirewallPortClose => (SERVICE_TRIGGER_TYPE_FIREWALL_PORT_EVENT, FIREWALL_PORT_CLOSE_GUID),
+            MachinePolicy => (SERVICE_TRIGGER_TYPE_GROUP_POLICY, MACHINE_POLICY_PRESENT_GUID),
+            UserPolicy => (SERVICE_TRIGGER_TYPE_GROUP_POLICY, USER_POLICY_PRESENT_GUID),
+            RpcInterface => (SERVICE_TRIGGER_TYPE_NETWORK_ENDPOINT, RPC_INTERFACE_EVENT_GUID),
+            NamedPipe => (SERVICE_TRIGGER_TYPE_NETWORK_ENDPOINT, NAMED_PIPE_EVENT_GUID),
+            DeviceInterfaceArrival(class) => (SERVICE_TRIGGER_TYPE_DEVICE_INTERFACE_ARRIVAL, class.into_raw()),
+            CustomSystemStateChange => (SERVICE_TRIGGER_TYPE_CUSTOM_SYSTEM_STATE_CHANGE, CUSTOM_SYSTEM_STATE_CHANGE_EVENT_GUID),
+            Custom(provider) => (SERVICE_TRIGGER_TYPE_CUSTOM, provider.into_raw()),
+        }
+    }
+}
+
+/// event specific data a trigger must match
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub enum TriggerData {
+    String(String),
+    /// stored as a multi string, matches any of the strings
+    Strings(Vec<String>),
+    Binary(Vec<u8>),
+    /// the maximum ETW event level
+    Level(u8),
+    /// ETW events must match at least one of these keywords
+    KeywordAny(u64),
+    /// ETW events must match all of these keywords
+    KeywordAll(u64),
+}
+
+impl TriggerData {
+    #[cfg_attr(not(windows), allow(dead_code))]
+    fn to_raw(&self) -> (u32, Vec<u8>) {
+        fn wide(strings: &[String]) -> Vec<u8> {
+            let mut bytes = Vec::new();
+            for string in strings {
+                for c in string.encode_utf16().chain(Some(0)) {
+                    bytes.extend_from_slice(&c.to_le_bytes());
+                }
+            }
+            bytes
+        }
+
+        match self {
+            Self::String(string) => (SERVICE_TRIGGER_DATA_TYPE_STRING, wide(std::slice::from_ref(string))),
+            Self::Strings(strings) => {
+                let mut bytes = wide(strings);
+                bytes.extend_from_slice(&[0,0]);
+                (SERVICE_TRIGGER_DATA_TYPE_STRING, bytes)
+            },
+            Self::Binary(bytes) => (SERVICE_TRIGGER_DATA_TYPE_BINARY, bytes.clone()),
+            Self::Level(level) => (SERVICE_TRIGGER_DATA_TYPE_LEVEL, vec![*level]),
+            Self::KeywordAny(keywords) => (SERVICE_TRIGGER_DATA_TYPE_KEYWORD_ANY, keywords.to_le_bytes().to_vec()),
+            Self::KeywordAll(keywords) => (SERVICE_TRIGGER_DATA_TYPE_KEYWORD_ALL, keywords.to_le_bytes().to_vec()),
+        }
+    }
+}
+
+/// start or stop a service when an event happens
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub struct Trigger {
+    pub action: TriggerAction,
+    pub event: TriggerEvent,
+    pub data: Vec<TriggerData>,
+}
+
+impl Trigger {
+    /// start the service when `event` happens
+    pub fn start(event: TriggerEvent) -> Self {
+        Self{ action: TriggerAction::Start, event, data: Vec::new() }
+    }
+
+    /// stop the service when `event` happens
+    pub fn stop(event: TriggerEvent) -> Self {
+        Self{ action: TriggerAction::Stop, event, data: Vec::new() }
+    }
+
+    /// only fire when the event data matches `data`
+    pub fn with_data(mut self, data: TriggerData) -> Self {
+        self.data.push(data);
+        self
+    }
+
+    /// start the service when a firewall port is opened
+    pub fn firewall_port_open(port: u16, protocol: &str) -> Self {
+        Self::start(TriggerEvent::FirewallPortOpen)
+            .with_data(TriggerData::Strings(vec![format!("{};{}", port, protocol)]))
+    }
+
+    /// start the service when a client connects to the named pipe
+    pub fn named_pipe(name: &str) -> Self {
+        Self::start(TriggerEvent::NamedPipe).with_data(TriggerData::String(name.into()))
+    }
+
+    /// start the service when a device with the hardware id arrives
+    pub fn device_arrival(class: Guid, hardware_id: &str) -> Self {
+        Self::start(TriggerEvent::DeviceInterfaceArrival(class))
+            .with_data(TriggerData::Strings(vec![hardware_id.into()]))
+    }
+}
+
+#[cfg(windows)]
+impl Service {
+    /// replace the start triggers of the service, an empty slice removes
+    /// them, requires `ServiceAccess::CHANGE_CONFIG`
+    pub fn set_triggers(&self, triggers: &[Trigger]) -> Result<()> {
+        tracing::trace!("setting service triggers: {:?}", triggers);
+
+        // everything the raw structures point into must outlive the call
+        let mut subtypes: Vec<GUID> = Vec::with_capacity(triggers.len());
+        let mut data: Vec<Vec<(u32,Vec<u8>)>> = Vec::with_capacity(triggers.len());
+        for trigger in triggers {
+            let (_, subtype) = trigger.event.into_raw();
+            subtypes.push(subtype);
+            data.push(trigger.data.iter().map(TriggerData::to_raw).collect());
+        }
+
+        let mut items: Vec<Vec<SERVICE_TRIGGER_SPECIFIC_DATA_ITEM>> = data.iter_mut()
+            .map(|data| data.iter_mut()
+                .map(|(data_type, bytes)| SERVICE_TRIGGER_SPECIFIC_DATA_ITEM{
+                    dwDataType: *data_type,
+                    cbData: bytes.len() as u32,
+                    pData: bytes.as_mut_ptr(),
+                })
+                .collect())
+            .collect();
+
+        let mut raw: Vec<SERVICE_TRIGGER> = triggers.iter()
+            .zip(subtypes.iter_mut())
+            .zip(items.iter_mut())
+            .map(|((trigger, subtype), items)| SERVICE_TRIGGER{
+                dwTriggerType: trigger.event.into_raw().0,
+                dwAction: match trigger.action {
+                    TriggerAction::Start => SERVICE_TRIGGER_ACTION_SERVICE_START,
+                    TriggerAction::Stop => SERVICE_TRIGGER_ACTION_SERVICE_STOP,
+                },
+                pTriggerSubtype: subtype,
+                cDataItems: items.len() as u32,
+                pDataItems: if items.is_empty() { null_mut() } else { items.as_mut_ptr() },
+            })
+            .collect();
+
+        let mut info = SERVICE_TRIGGER_INFO{
+            cTriggers: raw.len() as u32,
+            pTriggers: if raw.is_empty() { null_mut() } else { raw.as_mut_ptr() },
+            pReserved: null_mut(),
+        };
+
+        Error::check_true(unsafe {
+            ChangeServiceConfig2W(self.0, SERVICE_CONFIG_TRIGGER_INFO, &mut info as *mut _ as *mut _)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn guid_fields() {
+        let guid = Guid::from_u128(0x4f27f2de_14e2_430b_a549_7cd48cbc8245);
+        assert_eq!(guid.fields(), (0x4f27f2de, 0x14e2, 0x430b, [0xa5, 0x49, 0x7c, 0xd4, 0x8c, 0xbc, 0x82, 0x45]));
+    }
+
+    #[test]
+    fn data_encoding() {
+        assert_eq!(
+            TriggerData::String("ab".into()).to_raw(),
+            (SERVICE_TRIGGER_DATA_TYPE_STRING, vec![b'a', 0, b'b', 0, 0, 0]),
+        );
+        assert_eq!(
+            TriggerData::Strings(vec!["a".into(), "ü".into()]).to_raw(),
+            (SERVICE_TRIGGER_DATA_TYPE_STRING, vec![b'a', 0, 0, 0, 0xfc, 0, 0, 0, 0, 0]),
+        );
+        assert_eq!(TriggerData::Strings(Vec::new()).to_raw(), (SERVICE_TRIGGER_DATA_TYPE_STRING, vec![0, 0]));
+        assert_eq!(TriggerData::Binary(vec![1, 2, 3]).to_raw(), (SERVICE_TRIGGER_DATA_TYPE_BINARY, vec![1, 2, 3]));
+        assert_eq!(TriggerData::Level(4).to_raw(), (SERVICE_TRIGGER_DATA_TYPE_LEVEL, vec![4]));
+        assert_eq!(
+            TriggerData::KeywordAny(0x0102_0304_0506_0708).to_raw(),
+            (SERVICE_TRIGGER_DATA_TYPE_KEYWORD_ANY, vec![8, 7, 6, 5, 4, 3, 2, 1]),
+        );
+        assert_eq!(TriggerData::KeywordAll(1).to_raw(), (SERVICE_TRIGGER_DATA_TYPE_KEYWORD_ALL, vec![1, 0, 0, 0, 0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn helpers() {
+        assert_eq!(Trigger::firewall_port_open(8080, "TCP").data, [TriggerData::Strings(vec!["8080;TCP".into()])]);
+        assert_eq!(Trigger::named_pipe("hello").event, TriggerEvent::NamedPipe);
+        assert_eq!(Trigger::stop(TriggerEvent::DomainLeave).action, TriggerAction::Stop);
+    }
+}
@@ -3,6 +3,11 @@ use serde::{Serialize, de::DeserializeOwned};
 use structopt::{StructOpt,StructOptInternal};
 
 use super::service_configuration;
+use super::service_control_manager::{Service,ServiceAccess,StartType};
+use super::sddl::SecurityDescriptor;
+use super::account::Account;
+use super::service_name::{ServiceName,DisplayName};
+use super::service_trigger::Trigger;
 
 #[derive(StructOpt,Debug)]
 pub struct LoggingConfig {
@@ -16,66 +21,213 @@ pub struct LoggingConfig {
     log_filter: Option<String>,
 }
 
+#[derive(StructOpt,Debug)]
+pub struct RemoteConfig {
+
+    /// machine hosting the service, defaults to this one
+    #[structopt(long)]
+    machine: Option<String>,
+}
+
+#[derive(StructOpt,Debug)]
+pub struct InstallConfig {
+
+    /// SDDL security descriptor to apply to the installed service
+    #[structopt(long)]
+    security: Option<SecurityDescriptor>,
+
+    /// account to run as: LocalSystem, LocalService, NetworkService,
+    /// "NT SERVICE\<name>", a managed "DOMAIN\name$" or a user account
+    #[structopt(long)]
+    account: Option<String>,
+
+    /// password of the user account given with --account
+    #[structopt(long)]
+    password: Option<String>,
+
+    /// environment variable to set for the service, as NAME=VALUE
+    #[structopt(long = "env", parse(try_from_str = parse_variable))]
+    environment: Vec<(String,String)>,
+}
+
+fn parse_variable(variable: &str) -> Result<(String,String),String> {
+    match variable.find('=') {
+        Some(0) | None => Err(format!("expected NAME=VALUE: {}", variable)),
+        Some(index) => Ok((variable[..index].into(), variable[index + 1..].into())),
+    }
+}
+
+impl InstallConfig {
+    /// the account to install as, `NetworkService` unless given
+    pub fn account(&self) -> Account {
+        self.account_override().unwrap_or_default()
+    }
+
+    /// the account given on the command line, if any
+    pub fn account_override(&self) -> Option<Account> {
+        self.account.as_ref().map(|name|Account::from_name(name, self.password.clone()))
+    }
+
+    fn apply(&self, service_name: &ServiceName, service: &Service, triggers: &[Trigger]) {
+        if !self.environment.is_empty() {
+            service_configuration::set_environment(service_name, &self.environment)
+                .expect("to set service environment");
+        }
+
+        if let Some(security) = &self.security {
+            service.set_security(security).expect("to set service security");
+        }
+
+        if !triggers.is_empty() {
+            service.set_start_type(StartType::Demand).expect("to set service start type");
+            service.set_triggers(triggers).expect("to set service triggers");
+        }
+    }
+}
+
 pub trait ServiceDetail {
 
     const SERVICE_IDENTIFIER: &'static str;
     const SERVICE_DISPLAY_NAME: &'static str;
 
+    /// `SERVICE_IDENTIFIER` validated
+    fn service_name() -> ServiceName {
+        ServiceName::new(Self::SERVICE_IDENTIFIER).expect("SERVICE_IDENTIFIER to be a valid service name")
+    }
+
+    /// `SERVICE_DISPLAY_NAME` validated
+    fn display_name() -> DisplayName {
+        DisplayName::new(Self::SERVICE_DISPLAY_NAME).expect("SERVICE_DISPLAY_NAME to be a valid display name")
+    }
+
     type Config: Serialize+DeserializeOwned+fmt::Debug;
 
+    /// run in the console
+    ///
+    /// When `run-as-service` falls back to the console logging is already
+    /// initialized, so set any subscriber with `try_init`.
     fn run_local(svc_config: Self::Config);
     fn run_as_service(log_config: LoggingConfig);
 
-    fn install(svc_config: Self::Config, log_config: LoggingConfig) {
-        tracing_subscriber::fmt::init();
-
-        use crate::service_control_manager::*;
+    /// events that start the service, when any are given it is installed to
+    /// start on demand instead of at boot
+    fn triggers() -> Vec<Trigger> {
+        Vec::new()
+    }
 
-        let mut args = Vec::<&str>::with_capacity(5);
+    /// adapt configuration saved by an earlier version while upgrading
+    fn migrate_config(config: Self::Config) -> Self::Config {
+        config
+    }
 
-        args.push("run-as-service");
+    fn install(svc_config: Self::Config, log_config: LoggingConfig, install_config: InstallConfig) {
+        tracing_subscriber::fmt::init();
 
-        if let Some(log_file) = &log_config.log_file {
-            args.push("--log-file");
-            args.push(log_file);
-        }
+        use crate::service_control_manager::*;
 
-        if let Some(log_filter) = &log_config.log_filter {
-            args.push("--log-filter");
-            args.push(log_filter);
-        }
+        let args = log_config.service_arguments();
 
         tracing::trace!("args: {:?}", args);
         tracing::trace!("config: {:?}", svc_config);
 
-        ServiceControlManager::open_local(Access::All)
+        let service = ServiceControlManager::open_local(Access::CONNECT | Access::CREATE_SERVICE)
             .expect("to open service control manager")
             .create_self_service_simple(
-                Self::SERVICE_IDENTIFIER,
-                Self::SERVICE_DISPLAY_NAME,
+                &Self::service_name(),
+                &Self::display_name(),
                 &args,
-                NETWORK_SERVICE,
+                &install_config.account(),
             )
             .expect("to install self as service")
         ;
 
-        service_configuration::save(Self::SERVICE_IDENTIFIER, &svc_config)
+        install_config.apply(&Self::service_name(), &service, &Self::triggers());
+
+        service_configuration::save(&Self::service_name(), &svc_config)
             .expect("while saving service configuration");
     }
 
-    fn uninstall() {
+    /// point the installed service at this executable, keeping its service
+    /// control manager configuration, and restart it if it was running
+    fn upgrade(log_config: LoggingConfig, install_config: InstallConfig) {
         tracing_subscriber::fmt::init();
-        open_service(Self::SERVICE_IDENTIFIER).delete().expect("to delete service");
+
+        use crate::service_control_manager::*;
+
+        let mut access = ServiceAccess::QUERY_STATUS | ServiceAccess::START | ServiceAccess::STOP | ServiceAccess::CHANGE_CONFIG;
+        if install_config.security.is_some() {
+            access |= ServiceAccess::WRITE_DAC | ServiceAccess::WRITE_OWNER;
+        }
+
+        let service = RemoteConfig{machine: None}.open_service(&Self::service_name(), access);
+
+        let was_running = service.state().expect("to query the service state") != ServiceState::Stopped;
+        if was_running {
+            service.stop().expect("to stop the service");
+        }
+
+        let mut spec = ServiceSpec::new().arguments(&log_config.service_arguments());
+        if let Some(account) = install_config.account_override() {
+            spec = spec.account(account);
+        }
+        service.update(&spec).expect("to update the service");
+
+        install_config.apply(&Self::service_name(), &service, &Self::triggers());
+
+        let config = service_configuration::load::<Self::Config>(&Self::service_name())
+            .expect("while loading service configuration");
+        let config = Self::migrate_config(config);
+        tracing::trace!("config: {:?}", config);
+        service_configuration::save(&Self::service_name(), &config)
+            .expect("while saving service configuration");
+
+        if was_running {
+            service.start().expect("to start the service");
+        }
+    }
+
+    fn uninstall(purge: bool) {
+        tracing_subscriber::fmt::init();
+        let service = RemoteConfig{machine: None}.open_service(&Self::service_name(), ServiceAccess::DELETE);
+        service.delete().expect("to delete service");
+        if purge {
+            // the service key lingers while a handle is open, it may be gone already
+            crate::service_configuration::purge(&Self::service_name()).expect("to purge the configuration");
+        }
+    }
+
+    fn start(remote: RemoteConfig) {
+        tracing_subscriber::fmt::init();
+        remote.open_service(&Self::service_name(), ServiceAccess::START | ServiceAccess::QUERY_STATUS).start().expect("to start the service");
     }
 
-    fn start() {
+    fn stop(remote: RemoteConfig) {
         tracing_subscriber::fmt::init();
-        open_service(Self::SERVICE_IDENTIFIER).start().expect("to start the service");
+        remote.open_service(&Self::service_name(), ServiceAccess::STOP | ServiceAccess::QUERY_STATUS).stop().expect("to start the service");
     }
 
-    fn stop() {
+    fn status(remote: RemoteConfig) {
         tracing_subscriber::fmt::init();
-        open_service(Self::SERVICE_IDENTIFIER).stop().expect("to start the service");
+        let status = remote.open_service(&Self::service_name(), ServiceAccess::QUERY_STATUS).status().expect("to query the service status");
+        match status.process_id {
+            Some(process_id) => println!("{:?} (pid {})", status.state, process_id),
+            None => println!("{:?}", status.state),
+        }
+    }
+
+    fn pause(remote: RemoteConfig) {
+        tracing_subscriber::fmt::init();
+        remote.open_service(&Self::service_name(), ServiceAccess::PAUSE_CONTINUE | ServiceAccess::QUERY_STATUS).pause().expect("to pause the service");
+    }
+
+    fn resume(remote: RemoteConfig) {
+        tracing_subscriber::fmt::init();
+        remote.open_service(&Self::service_name(), ServiceAccess::PAUSE_CONTINUE | ServiceAccess::QUERY_STATUS).resume().expect("to continue the service");
+    }
+
+    fn control(code: u8, remote: RemoteConfig) {
+        tracing_subscriber::fmt::init();
+        remote.open_service(&Self::service_name(), ServiceAccess::USER_DEFINED_CONTROL).send_control(code).expect("to send control code to the service");
     }
 }
 
@@ -94,18 +246,53 @@ pub enum Command<S:ServiceDetail> where <S as ServiceDetail>::Config: StructOpt
 
         #[structopt(flatten)]
         log_config: LoggingConfig,
+
+        #[structopt(flatten)]
+        install_config: InstallConfig,
+    },
+
+    /// replace the installed service with this executable, keeping its
+    /// configuration
+    Upgrade{
+        #[structopt(flatten)]
+        log_config: LoggingConfig,
+
+        #[structopt(flatten)]
+        install_config: InstallConfig,
     },
 
     /// uninstall as a windows service
-    Uninstall,
+    Uninstall{
+        /// also delete the stored service configuration
+        #[structopt(long)]
+        purge: bool,
+    },
 
     /// start the previously installed service
-    Start,
+    Start(RemoteConfig),
 
     /// stop the previously installed and started service
-    Stop,
+    Stop(RemoteConfig),
+
+    /// show the current state of the installed service
+    Status(RemoteConfig),
+
+    /// pause the running service
+    Pause(RemoteConfig),
 
-    /// invoked by windows when started as a service [will fail if used elsewhere]
+    /// continue the paused service
+    Continue(RemoteConfig),
+
+    /// send a user defined control code (128-255) to the running service
+    Control{
+        code: u8,
+
+        #[structopt(flatten)]
+        remote: RemoteConfig,
+    },
+
+    /// invoked by windows when started as a service, runs in the console
+    /// with the installed configuration when used elsewhere
     RunAsService(LoggingConfig),
 }
 
@@ -114,16 +301,50 @@ impl<S:ServiceDetail> Command<S> where <S as ServiceDetail>::Config: StructOpt +
         use Command::*;
         match Self::from_args() {
             Run(svc_config) => S::run_local(svc_config),
-            RunAsService(log_config) => S::run_as_service(log_config),
-            Install{svc_config,log_config} => S::install(svc_config,log_config),
-            Uninstall => S::uninstall(),
-            Start => S::start(),
-            Stop => S::stop(),
+            RunAsService(log_config) => {
+                if crate::is_running_as_service() {
+                    S::run_as_service(log_config)
+                } else {
+                    // started from a console, run with the installed configuration
+                    log_config.init();
+                    let svc_config = service_configuration::load(&S::service_name())
+                        .expect("while loading service configuration");
+                    S::run_local(svc_config)
+                }
+            },
+            Install{svc_config,log_config,install_config} => S::install(svc_config,log_config,install_config),
+            Upgrade{log_config,install_config} => S::upgrade(log_config,install_config),
+            Uninstall{purge} => S::uninstall(purge),
+            Start(remote) => S::start(remote),
+            Stop(remote) => S::stop(remote),
+            Status(remote) => S::status(remote),
+            Pause(remote) => S::pause(remote),
+            Continue(remote) => S::resume(remote),
+            Control{code,remote} => S::control(code,remote),
         }
     }
 }
 
 impl LoggingConfig {
+    /// the arguments the service control manager starts the service with
+    fn service_arguments(&self) -> Vec<&str> {
+        let mut args = Vec::<&str>::with_capacity(5);
+
+        args.push("run-as-service");
+
+        if let Some(log_file) = &self.log_file {
+            args.push("--log-file");
+            args.push(log_file);
+        }
+
+        if let Some(log_filter) = &self.log_filter {
+            args.push("--log-filter");
+            args.push(log_filter);
+        }
+
+        args
+    }
+
     pub fn init(self) {
         if let Some(log_file) = self.log_file {
             use tracing_subscriber::fmt::*;
@@ -148,11 +369,13 @@ impl LoggingConfig {
     }
 }
 
-fn open_service(name: &str) -> crate::service_control_manager::Service {
+impl RemoteConfig {
+    fn open_service(&self, name: &ServiceName, access: ServiceAccess) -> crate::service_control_manager::Service {
 
-    use crate::service_control_manager::*;
+        use crate::service_control_manager::*;
 
-    let mut scm = ServiceControlManager::open_local(Access::All).expect("to open service control manager");
+        let mut scm = ServiceControlManager::open(self.machine.as_deref(), Access::CONNECT).expect("to open service control manager");
 
-    scm.open_service(name).expect("to open service")
+        scm.open_service(name, access).expect("to open service")
+    }
 }